@@ -0,0 +1,132 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{CaptureMatcher, QueryStampPart, Stamp, StampPart};
+
+/// A placement of a query where at most a given number of cells didn't match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ApproxMatch {
+    pub position: (usize, usize),
+    /// The coordinates, in the searched stamp, of the cells that didn't match
+    /// in row-major order. Its length is the mismatch count.
+    pub mismatches: Vec<(usize, usize)>,
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// The cells that don't match `query` at the given position, checking
+    /// captures as `find` does, or `None` once there are more than
+    /// `max_mismatches` of them.
+    pub(crate) fn mismatches_at_position(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        pos_x: usize,
+        pos_y: usize,
+        max_mismatches: usize,
+    ) -> Option<Vec<(usize, usize)>> {
+        let mut matcher = CaptureMatcher::new();
+        let mut mismatches = Vec::new();
+        let mut same_as = Vec::new();
+        for (query_y, query_row) in query.rows().enumerate() {
+            let this_row = &self.cells[self.index(pos_x, pos_y + query_y)..];
+            for (query_x, (query_part, col)) in query_row.iter().zip(this_row).enumerate() {
+                let coord = (pos_x + query_x, pos_y + query_y);
+                if let QueryStampPart::SameAs(_) = query_part {
+                    same_as.push(coord);
+                }
+                if !matcher.check(query_part, col) {
+                    mismatches.push(coord);
+                    if mismatches.len() > max_mismatches {
+                        return None;
+                    }
+                }
+            }
+        }
+        mismatches.extend(matcher.failed_same_as().map(|position| same_as[position]));
+        if mismatches.len() > max_mismatches {
+            return None;
+        }
+        mismatches.sort_by_key(|&(x, y)| (y, x));
+        Some(mismatches)
+    }
+
+    /// Finds every placement of `query` where no more than `max_mismatches`
+    /// cells fail to match, in row-major order. With no mismatches allowed
+    /// this finds the same positions as `find`.
+    pub fn find_approx(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        max_mismatches: usize,
+    ) -> Vec<ApproxMatch> {
+        let (x_range, y_range) = self.origin_ranges(query);
+        let mut matches = Vec::new();
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(mismatches) = self.mismatches_at_position(query, x, y, max_mismatches) {
+                    matches.push(ApproxMatch {
+                        position: (x, y),
+                        mismatches,
+                    });
+                }
+            }
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Capture, Is, SameAs};
+    use StampPart::Use;
+
+    fn stamp() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(1), Use(1), Use(0)],
+            vec![Use(1), Use(0), Use(1)],
+            vec![Use(0), Use(0), Use(1)],
+        ])
+    }
+
+    #[test]
+    fn it_should_report_the_cells_that_did_not_match() {
+        let query_stamp = Stamp::new(vec![
+            vec![Is(Box::new([1])), Is(Box::new([1]))],
+            vec![Is(Box::new([1])), Is(Box::new([1]))],
+        ]);
+        let result = stamp().find_approx(&query_stamp, 1);
+        let expected = vec![ApproxMatch {
+            position: (0, 0),
+            mismatches: vec![(1, 1)],
+        }];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_should_match_find_with_no_mismatches_allowed() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([0]))], vec![Is(Box::new([1]))]]);
+        let result: Vec<(usize, usize)> = stamp()
+            .find_approx(&query_stamp, 0)
+            .into_iter()
+            .map(|approx| approx.position)
+            .collect();
+        assert_eq!(result, stamp().find(&query_stamp));
+    }
+
+    #[test]
+    fn it_should_count_failed_back_references_as_mismatches() {
+        let query_stamp = Stamp::new(vec![vec![SameAs('a'), Capture('a'), SameAs('a')]]);
+        let result = stamp().find_approx(&query_stamp, 1);
+        let expected = vec![
+            ApproxMatch {
+                position: (0, 0),
+                mismatches: vec![(2, 0)],
+            },
+            ApproxMatch {
+                position: (0, 2),
+                mismatches: vec![(2, 2)],
+            },
+        ];
+        assert_eq!(result, expected);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use crate::{Rect, Stamp, StampPart, Transform};
+
+/// Combines an existing cell with a source cell, in that order.
+pub type Merge<T> = dyn Fn(&StampPart<T>, &StampPart<T>) -> StampPart<T>;
+
+/// How `Stamp::stamp_with` combines each source cell with the cell beneath it.
+pub enum BlendMode<'a, T: Clone + PartialEq> {
+    /// `Use` cells overwrite and `Transparent` cells are skipped, as with
+    /// `Stamp::stamp`.
+    Overwrite,
+    /// `Use` cells are written only over `Transparent` cells.
+    OnlyOverTransparent,
+    /// `Use` cells are written only over `Use` cells.
+    OnlyOverOpaque,
+    /// `Use` cells make the cell beneath them `Transparent`.
+    Erase,
+    /// Every cell, `Transparent` or not, is replaced by the closure's result.
+    Merge(&'a Merge<T>),
+}
+
+impl<T: Clone + PartialEq> fmt::Debug for BlendMode<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlendMode::Overwrite => write!(f, "Overwrite"),
+            BlendMode::OnlyOverTransparent => write!(f, "OnlyOverTransparent"),
+            BlendMode::OnlyOverOpaque => write!(f, "OnlyOverOpaque"),
+            BlendMode::Erase => write!(f, "Erase"),
+            BlendMode::Merge(merge) => write!(f, "Merge({:p})", *merge),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> BlendMode<'_, T> {
+    /// The cell to write over `existing`, or `None` to leave it.
+    fn blend(&self, existing: &StampPart<T>, source: &StampPart<T>) -> Option<StampPart<T>> {
+        if let BlendMode::Merge(merge) = self {
+            return Some(merge(existing, source));
+        }
+        if *source == StampPart::Transparent {
+            return None;
+        }
+        match (self, existing) {
+            (BlendMode::OnlyOverTransparent, StampPart::Use(_)) => None,
+            (BlendMode::OnlyOverOpaque, StampPart::Transparent) => None,
+            (BlendMode::Erase, _) => Some(StampPart::Transparent),
+            _ => Some(source.clone()),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Stamps `stamp` with `transform` applied to it and its top left corner
+    /// at `(pos_x, pos_y)`, combining cells according to `mode`. Every other
+    /// clipped stamping method is built on this.
+    pub(crate) fn stamp_blended(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        transform: Transform,
+        mode: BlendMode<T>,
+    ) -> Option<Rect> {
+        let (width, height) = transform.dimensions(stamp.width(), stamp.height());
+        let region = self.clip(pos_x, pos_y, width, height)?;
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let (src_x, src_y) = transform.source_coord(
+                    (x as isize - pos_x) as usize,
+                    (y as isize - pos_y) as usize,
+                    stamp.width(),
+                    stamp.height(),
+                );
+                let source = &stamp.cells[stamp.index(src_x, src_y)];
+                let index = self.index(x, y);
+                if let Some(part) = mode.blend(&self.cells[index], source) {
+                    self.cells[index] = part;
+                }
+            }
+        }
+        Some(region)
+    }
+
+    /// Stamps `stamp` with its top left corner at `(pos_x, pos_y)`, combining
+    /// each cell with the one beneath it according to `mode`. Cells that land
+    /// outside of this stamp are clipped as with `stamp_clipped`, and the
+    /// overlapped region is returned.
+    pub fn stamp_with(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        mode: BlendMode<T>,
+    ) -> Option<Rect> {
+        self.stamp_blended(stamp, pos_x, pos_y, Transform::Identity, mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use StampPart::{Transparent, Use};
+
+    fn target() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![vec![Use(1), Transparent, Use(2)]])
+    }
+
+    fn brush() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![vec![Use(5), Use(5), Transparent]])
+    }
+
+    fn stamped_with(mode: BlendMode<i32>) -> Stamp<StampPart<i32>> {
+        let mut stamp = target();
+        stamp.stamp_with(&brush(), 0, 0, mode);
+        stamp
+    }
+
+    #[test]
+    fn overwrite_should_match_stamp() {
+        let mut expected = target();
+        expected.stamp(&brush(), 0, 0);
+        assert_eq!(stamped_with(BlendMode::Overwrite), expected);
+    }
+
+    #[test]
+    fn only_over_modes_should_check_the_cell_beneath() {
+        assert_eq!(
+            stamped_with(BlendMode::OnlyOverTransparent),
+            Stamp::new(vec![vec![Use(1), Use(5), Use(2)]])
+        );
+        assert_eq!(
+            stamped_with(BlendMode::OnlyOverOpaque),
+            Stamp::new(vec![vec![Use(5), Transparent, Use(2)]])
+        );
+    }
+
+    #[test]
+    fn erase_should_clear_cells_under_used_cells() {
+        assert_eq!(
+            stamped_with(BlendMode::Erase),
+            Stamp::new(vec![vec![Transparent, Transparent, Use(2)]])
+        );
+    }
+
+    #[test]
+    fn merge_should_combine_every_cell() {
+        let add = |existing: &StampPart<i32>, source: &StampPart<i32>| match (existing, source) {
+            (Use(a), Use(b)) => Use(a + b),
+            (Transparent, part) | (part, Transparent) => part.clone(),
+        };
+        let mut stamp = target();
+        let result = stamp.stamp_with(&brush(), 1, 0, BlendMode::Merge(&add));
+        assert_eq!(result, Some(Rect::new(1, 0, 2, 1)));
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(5), Use(7)]]));
+    }
+}
@@ -0,0 +1,234 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{CaptureMatcher, QueryStampPart, Stamp, StampPart};
+
+/// What a search sees when a query cell falls outside of the stamp.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Boundary<T: Clone + PartialEq> {
+    /// Cells outside of the stamp only match `QueryStampPart::OutOfBounds`.
+    Fail,
+    /// Cells outside of the stamp hold the given value.
+    TreatAs(T),
+    /// Coordinates wrap around to the opposite edge. Nothing is out of bounds,
+    /// so `QueryStampPart::OutOfBounds` never matches.
+    Wrap,
+    /// Coordinates are clamped to the nearest edge cell.
+    Clamp,
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Looks up the cell at a possibly out-of-bounds coordinate, returning
+    /// `None` if it is outside of the stamp and `boundary` doesn't supply one.
+    fn boundary_part<'a>(
+        &'a self,
+        x: isize,
+        y: isize,
+        boundary: &'a Boundary<StampPart<T>>,
+    ) -> Option<&'a StampPart<T>> {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let (x, y) = if x >= 0 && y >= 0 && x < width && y < height {
+            (x, y)
+        } else {
+            match boundary {
+                Boundary::Fail => return None,
+                Boundary::TreatAs(part) => return Some(part),
+                Boundary::Wrap => (x.rem_euclid(width), y.rem_euclid(height)),
+                Boundary::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+            }
+        };
+        Some(&self.cells[self.index(x as usize, y as usize)])
+    }
+
+    pub(crate) fn find_at_position_with_boundary(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        boundary: &Boundary<StampPart<T>>,
+    ) -> bool {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let mut matcher = CaptureMatcher::new();
+        for (query_y, query_row) in query.rows().enumerate() {
+            let y = pos_y + query_y as isize;
+            for (query_x, query_part) in query_row.iter().enumerate() {
+                let x = pos_x + query_x as isize;
+                let outside = x < 0 || y < 0 || x >= width || y >= height;
+                let matched = match query_part {
+                    QueryStampPart::OutOfBounds => outside && *boundary != Boundary::Wrap,
+                    _ => match self.boundary_part(x, y, boundary) {
+                        Some(part) => matcher.check(query_part, part),
+                        None => false,
+                    },
+                };
+                if !matched {
+                    return false;
+                }
+            }
+        }
+        matcher.finish().is_some()
+    }
+
+    /// Finds `query` using `boundary` for any cells that fall outside of this
+    /// stamp, in row-major order.
+    ///
+    /// Unless `boundary` is `Boundary::Wrap`, every origin at which the query
+    /// overlaps the stamp is tried, so positions may be negative. With
+    /// `Boundary::Wrap` each origin inside the stamp is tried once, including
+    /// those where the query straddles an edge.
+    pub fn find_with_boundary(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        boundary: &Boundary<StampPart<T>>,
+    ) -> Vec<(isize, isize)> {
+        let mut matches = Vec::new();
+        if self.as_slice().is_empty() {
+            return matches;
+        }
+        let (start_x, start_y) = match boundary {
+            Boundary::Wrap => (0, 0),
+            _ => (
+                1 - query.width().max(1) as isize,
+                1 - query.height().max(1) as isize,
+            ),
+        };
+        for y in start_y..self.height() as isize {
+            for x in start_x..self.width() as isize {
+                if self.find_at_position_with_boundary(query, x, y, boundary) {
+                    matches.push((x, y));
+                }
+            }
+        }
+        matches
+    }
+
+    /// Finds `query` treating this stamp as a torus, trying every origin once
+    /// including those where the query wraps across an edge.
+    pub fn find_wrapping(&self, query: &Stamp<QueryStampPart<T>>) -> Vec<(usize, usize)> {
+        self.find_with_boundary(query, &Boundary::Wrap)
+            .into_iter()
+            .map(|(x, y)| (x as usize, y as usize))
+            .collect()
+    }
+
+    /// Stamps `stamp` treating this stamp as a torus, so parts that pass an
+    /// edge continue from the opposite edge. Positions are taken modulo the
+    /// width and height.
+    pub fn stamp_wrapping(&mut self, stamp: &Stamp<StampPart<T>>, pos_x: usize, pos_y: usize) {
+        if self.as_slice().is_empty() {
+            return;
+        }
+        let (width, height) = (self.width(), self.height());
+        let (pos_x, pos_y) = (pos_x % width, pos_y % height);
+        for (stamp_y, row) in stamp.rows().enumerate() {
+            for (stamp_x, stamp_pattern_element) in row.iter().enumerate() {
+                if let StampPart::Use(_) = stamp_pattern_element {
+                    let index = self.index(
+                        (pos_x + stamp_x % width) % width,
+                        (pos_y + stamp_y % height) % height,
+                    );
+                    self.cells[index] = stamp_pattern_element.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Any, Is, OutOfBounds};
+    use StampPart::Use;
+
+    fn stamp() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(1), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(2)],
+        ])
+    }
+
+    #[test]
+    fn fail_should_match_the_same_positions_as_find() {
+        let query_stamp = Stamp::new(vec![vec![Any, Is(Box::new([0]))]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::Fail);
+        let expected: Vec<(isize, isize)> = stamp()
+            .find(&query_stamp)
+            .into_iter()
+            .map(|(x, y)| (x as isize, y as isize))
+            .collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn out_of_bounds_cells_should_match_past_the_edge() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([2])), OutOfBounds]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::Fail);
+        assert_eq!(result, vec![(2, 1)]);
+        let query_stamp = Stamp::new(vec![vec![OutOfBounds], vec![Is(Box::new([1]))]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::Fail);
+        assert_eq!(result, vec![(0, -1)]);
+    }
+
+    #[test]
+    fn treat_as_should_supply_a_value_outside_of_the_stamp() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([9])), Is(Box::new([1]))]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::TreatAs(Use(9)));
+        assert_eq!(result, vec![(-1, 0)]);
+    }
+
+    #[test]
+    fn wrap_should_read_from_the_opposite_edge() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([2])), Is(Box::new([0]))]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::Wrap);
+        assert_eq!(result, vec![(2, 1)]);
+        let query_stamp = Stamp::new(vec![vec![OutOfBounds]]);
+        assert!(stamp()
+            .find_with_boundary(&query_stamp, &Boundary::Wrap)
+            .is_empty());
+    }
+
+    #[test]
+    fn find_wrapping_should_match_across_the_seams() {
+        let query_stamp = Stamp::new(vec![
+            vec![Is(Box::new([2])), Is(Box::new([0]))],
+            vec![Is(Box::new([0])), Is(Box::new([1]))],
+        ]);
+        let result = stamp().find_wrapping(&query_stamp);
+        assert_eq!(result, vec![(2, 1)]);
+    }
+
+    #[test]
+    fn stamp_wrapping_should_continue_from_the_opposite_edge() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 3]; 2]);
+        let replace_stamp = Stamp::new(vec![
+            vec![Use(1), Use(2)],
+            vec![Use(3), StampPart::Transparent],
+        ]);
+        stamp.stamp_wrapping(&replace_stamp, 2, 1);
+        let expected = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(3)],
+            vec![Use(2), Use(0), Use(1)],
+        ]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn stamp_wrapping_should_take_huge_positions_modulo_the_size() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 3]; 2]);
+        let replace_stamp = Stamp::new(vec![vec![Use(1), Use(2)]]);
+        stamp.stamp_wrapping(&replace_stamp, usize::MAX, usize::MAX);
+        let expected = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(1), Use(2), Use(0)],
+        ]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn clamp_should_read_from_the_nearest_edge() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([1])), Is(Box::new([1]))]]);
+        let result = stamp().find_with_boundary(&query_stamp, &Boundary::Clamp);
+        assert_eq!(result, vec![(-1, 0)]);
+    }
+}
@@ -0,0 +1,172 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{QueryStampPart, Stamp, StampPart};
+
+/// The cells bound by the `QueryStampPart::Capture` cells of one match.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Captures<T: Clone + PartialEq> {
+    bindings: Vec<(char, StampPart<T>)>,
+}
+
+impl<T: Clone + PartialEq> Captures<T> {
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+    pub fn get(&self, name: char) -> Option<&StampPart<T>> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == name)
+            .map(|(_, part)| part)
+    }
+    pub fn insert(&mut self, name: char, part: StampPart<T>) {
+        match self.bindings.iter_mut().find(|(bound, _)| *bound == name) {
+            Some(binding) => binding.1 = part,
+            None => self.bindings.push((name, part)),
+        }
+    }
+    /// Iterates over the bindings in the order they were captured.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &StampPart<T>)> {
+        self.bindings.iter().map(|(name, part)| (*name, part))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+}
+
+impl<T: Clone + PartialEq> Default for Captures<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks the cells of one placement of a query in turn, tracking captures.
+/// `SameAs` cells are checked once every cell has been seen, so they may
+/// refer to captures that come later in the query.
+pub(crate) struct CaptureMatcher<'a, T: Clone + PartialEq> {
+    captures: Captures<T>,
+    pending: Vec<(char, &'a StampPart<T>)>,
+}
+
+impl<'a, T: Clone + PartialEq> CaptureMatcher<'a, T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            captures: Captures::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub(crate) fn check(&mut self, query_part: &QueryStampPart<T>, part: &'a StampPart<T>) -> bool {
+        match query_part {
+            QueryStampPart::Capture(name) => match self.captures.get(*name) {
+                Some(bound) => bound == part,
+                None => {
+                    self.captures.insert(*name, part.clone());
+                    true
+                }
+            },
+            QueryStampPart::SameAs(name) => {
+                self.pending.push((*name, part));
+                true
+            }
+            _ => query_part.matches(part),
+        }
+    }
+
+    /// The positions, in the order they were checked, of the `SameAs` cells
+    /// that don't equal their capture.
+    pub(crate) fn failed_same_as(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter(move |(_, (name, part))| self.captures.get(*name) != Some(*part))
+            .map(|(position, _)| position)
+    }
+
+    pub(crate) fn finish(self) -> Option<Captures<T>> {
+        for (name, part) in self.pending {
+            if self.captures.get(name) != Some(part) {
+                return None;
+            }
+        }
+        Some(self.captures)
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Finds `query` in row-major order, returning each match with the cells
+    /// bound by its capture cells.
+    pub fn find_captures(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+    ) -> Vec<((usize, usize), Captures<T>)> {
+        let (x_range, y_range) = self.origin_ranges(query);
+        let mut matches = Vec::new();
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(captures) = self.captures_at_position(query, x, y) {
+                    matches.push(((x, y), captures));
+                }
+            }
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Capture, Is, SameAs};
+    use StampPart::Use;
+
+    fn stamp() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(1), Use(1), Use(2)],
+            vec![Use(1), Use(1), Use(2)],
+            vec![Use(3), Use(2), Use(2)],
+        ])
+    }
+
+    #[test]
+    fn capture_cells_with_the_same_name_should_match_equal_cells() {
+        let query_stamp = Stamp::new(vec![
+            vec![Capture('a'), Capture('a')],
+            vec![Capture('a'), Capture('a')],
+        ]);
+        let result = stamp().find_captures(&query_stamp);
+        let mut captures = Captures::new();
+        captures.insert('a', Use(1));
+        assert_eq!(result, vec![((0, 0), captures)]);
+        assert_eq!(stamp().find(&query_stamp), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn same_as_should_compare_against_a_later_capture() {
+        let query_stamp = Stamp::new(vec![vec![SameAs('a')], vec![Capture('a')]]);
+        let result: Vec<(usize, usize)> = stamp()
+            .find_captures(&query_stamp)
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect();
+        assert_eq!(result, vec![(0, 0), (1, 0), (2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn it_should_return_every_binding_of_a_match() {
+        let query_stamp = Stamp::new(vec![vec![Capture('a'), Is(Box::new([2])), Capture('b')]]);
+        let small = Stamp::new(vec![vec![Use(7), Use(2), Use(8)]]);
+        let result = small.find_captures(&query_stamp);
+        assert_eq!(result.len(), 1);
+        let bindings: Vec<(char, &StampPart<i32>)> = result[0].1.iter().collect();
+        assert_eq!(bindings, vec![('a', &Use(7)), ('b', &Use(8))]);
+    }
+
+    #[test]
+    fn same_as_should_fail_if_the_name_is_never_captured() {
+        let query_stamp = Stamp::new(vec![vec![SameAs('z')]]);
+        assert!(stamp().find_captures(&query_stamp).is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{QueryStampPart, Stamp, StampPart};
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// The total of `cost` over every cell of `query` placed at the given
+    /// position, or `None` if it doesn't fit inside this stamp.
+    pub fn score_at<F>(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        pos_x: usize,
+        pos_y: usize,
+        cost: F,
+    ) -> Option<f64>
+    where
+        F: Fn(&QueryStampPart<T>, &StampPart<T>) -> f64,
+    {
+        if !self.fits(pos_x, pos_y, query.width(), query.height()) {
+            return None;
+        }
+        let mut total = 0.0;
+        for (query_y, query_row) in query.rows().enumerate() {
+            let this_row = &self.cells[self.index(pos_x, pos_y + query_y)..];
+            for (query_part, col) in query_row.iter().zip(this_row) {
+                total += cost(query_part, col);
+            }
+        }
+        Some(total)
+    }
+
+    /// Scores every placement of `query` with `cost`, in row-major order.
+    pub fn scores<F>(&self, query: &Stamp<QueryStampPart<T>>, cost: F) -> Vec<((usize, usize), f64)>
+    where
+        F: Fn(&QueryStampPart<T>, &StampPart<T>) -> f64,
+    {
+        let (x_range, y_range) = self.origin_ranges(query);
+        let mut scores = Vec::new();
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(score) = self.score_at(query, x, y, &cost) {
+                    scores.push(((x, y), score));
+                }
+            }
+        }
+        scores
+    }
+
+    /// The `n` placements of `query` with the lowest total `cost`, cheapest
+    /// first. Placements with equal cost are in row-major order.
+    pub fn best_fit_by<F>(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        n: usize,
+        cost: F,
+    ) -> Vec<((usize, usize), f64)>
+    where
+        F: Fn(&QueryStampPart<T>, &StampPart<T>) -> f64,
+    {
+        let mut scores = self.scores(query, cost);
+        scores.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        scores.truncate(n);
+        scores
+    }
+
+    /// The `n` placements of `query` with the fewest cells that don't match,
+    /// best first. Cells are counted as `find_approx` counts them, so the
+    /// placements that score zero are exactly those `find` returns.
+    pub fn best_fit(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        n: usize,
+    ) -> Vec<((usize, usize), f64)> {
+        let (x_range, y_range) = self.origin_ranges(query);
+        let mut scores = Vec::new();
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(mismatches) = self.mismatches_at_position(query, x, y, usize::MAX) {
+                    scores.push(((x, y), mismatches.len() as f64));
+                }
+            }
+        }
+        scores.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        scores.truncate(n);
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Any, Capture, Is, SameAs};
+    use StampPart::Use;
+
+    fn terrain() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(0), Use(0), Use(5)],
+            vec![Use(0), Use(3), Use(5)],
+            vec![Use(1), Use(1), Use(5)],
+        ])
+    }
+
+    #[test]
+    fn best_fit_should_rank_placements_by_mismatches() {
+        let room = Stamp::new(vec![vec![Is(Box::new([0])), Is(Box::new([0]))]; 2]);
+        let result = terrain().best_fit(&room, 3);
+        let expected = vec![((0, 0), 1.0), ((1, 0), 3.0), ((0, 1), 3.0)];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn best_fit_by_should_use_the_given_cost() {
+        let room = Stamp::new(vec![vec![Any, Any]; 2]);
+        let height_change = |_: &QueryStampPart<i32>, part: &StampPart<i32>| match part {
+            Use(height) => *height as f64,
+            StampPart::Transparent => 0.0,
+        };
+        let result = terrain().best_fit_by(&room, 1, height_change);
+        assert_eq!(result, vec![((0, 0), 3.0)]);
+        assert_eq!(terrain().scores(&room, height_change).len(), 4);
+    }
+
+    #[test]
+    fn score_at_should_be_none_if_the_query_does_not_fit() {
+        let room = Stamp::new(vec![vec![Any, Any]]);
+        assert_eq!(terrain().score_at(&room, 2, 0, |_, _| 1.0), None);
+    }
+
+    #[test]
+    fn best_fit_should_score_zero_exactly_where_find_matches() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(2), Use(2)],
+            vec![Use(3), Use(3), Use(1)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![Capture('a'), SameAs('a')]]);
+        let perfect: Vec<(usize, usize)> = stamp
+            .best_fit(&query_stamp, usize::MAX)
+            .into_iter()
+            .filter(|(_, score)| *score == 0.0)
+            .map(|(position, _)| position)
+            .collect();
+        assert_eq!(perfect, stamp.find(&query_stamp));
+        let pair = Stamp::new(vec![vec![Use(1), Use(2)]]);
+        assert_eq!(pair.best_fit(&query_stamp, 1), vec![((0, 0), 1.0)]);
+    }
+}
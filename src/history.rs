@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+
+use crate::{Rect, Stamp, StampError, StampPart};
+
+/// One undoable step: every cell it changed as `(index, before, after)`, in
+/// the order they were written.
+#[derive(Debug, Clone, PartialEq)]
+struct Edit<T: Clone + PartialEq> {
+    changes: Vec<(usize, StampPart<T>, StampPart<T>)>,
+}
+
+/// Wraps a stamp and records the cells overwritten by each operation so that
+/// they can be undone and redone.
+///
+/// Only changed cells are kept. Once more than the memory budget's worth of
+/// cells is recorded the oldest steps are forgotten.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History<T: Clone + PartialEq> {
+    stamp: Stamp<StampPart<T>>,
+    undo: VecDeque<Edit<T>>,
+    redo: Vec<Edit<T>>,
+    /// Changes made inside the open transaction, if there is one.
+    open: Vec<(usize, StampPart<T>, StampPart<T>)>,
+    depth: usize,
+    budget: usize,
+    recorded: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    /// Starts recording changes to `stamp`, with no limit on the memory used.
+    pub fn new(stamp: Stamp<StampPart<T>>) -> Self {
+        Self {
+            stamp,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            open: Vec::new(),
+            depth: 0,
+            budget: usize::MAX,
+            recorded: 0,
+        }
+    }
+
+    /// Keeps at most `cells` changed cells across the undo history, forgetting
+    /// the oldest steps first. A single step larger than this can't be undone.
+    pub fn with_budget(mut self, cells: usize) -> Self {
+        self.budget = cells;
+        self.trim();
+        self
+    }
+
+    pub fn current(&self) -> &Stamp<StampPart<T>> {
+        &self.stamp
+    }
+
+    pub fn into_stamp(self) -> Stamp<StampPart<T>> {
+        self.stamp
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forgets every recorded step without changing the stamp, including the
+    /// changes made so far inside an open transaction.
+    pub fn clear(&mut self) {
+        self.open.clear();
+        self.undo.clear();
+        self.redo.clear();
+        self.recorded = 0;
+    }
+
+    fn trim(&mut self) {
+        while self.recorded > self.budget {
+            match self.undo.pop_front() {
+                Some(edit) => self.recorded -= edit.changes.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn push(&mut self, changes: Vec<(usize, StampPart<T>, StampPart<T>)>) {
+        if changes.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.recorded += changes.len();
+        self.undo.push_back(Edit { changes });
+        self.trim();
+    }
+
+    /// Runs `operation`, recording the cells of `region` that it changed.
+    /// `region` must cover every cell the operation could write.
+    fn record<R>(
+        &mut self,
+        region: Option<Rect>,
+        operation: impl FnOnce(&mut Stamp<StampPart<T>>) -> R,
+    ) -> R {
+        let region = match region {
+            Some(region) => region,
+            None => return operation(&mut self.stamp),
+        };
+        let indices: Vec<usize> = (region.y..region.y + region.height)
+            .flat_map(|y| (region.x..region.x + region.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.stamp.index(x, y))
+            .collect();
+        let before: Vec<StampPart<T>> = indices
+            .iter()
+            .map(|&index| self.stamp.cells[index].clone())
+            .collect();
+        let result = operation(&mut self.stamp);
+        let changes: Vec<(usize, StampPart<T>, StampPart<T>)> = indices
+            .into_iter()
+            .zip(before)
+            .filter(|(index, before)| self.stamp.cells[*index] != *before)
+            .map(|(index, before)| (index, before, self.stamp.cells[index].clone()))
+            .collect();
+        if self.depth > 0 {
+            self.open.extend(changes);
+        } else {
+            self.push(changes);
+        }
+        result
+    }
+
+    /// Runs `edits` as a single step, so that everything it changes is undone
+    /// and redone together. Transactions may be nested.
+    pub fn transaction<R>(&mut self, edits: impl FnOnce(&mut Self) -> R) -> R {
+        self.depth += 1;
+        let result = edits(self);
+        self.depth -= 1;
+        if self.depth == 0 {
+            let changes = std::mem::take(&mut self.open);
+            self.push(changes);
+        }
+        result
+    }
+
+    /// The smallest region covering every cell of `changes`.
+    fn bounds(&self, changes: &[(usize, StampPart<T>, StampPart<T>)]) -> Option<Rect> {
+        let width = self.stamp.width();
+        let (mut left, mut top) = (usize::MAX, usize::MAX);
+        let (mut right, mut bottom) = (0, 0);
+        for &(index, _, _) in changes {
+            let (x, y) = (index % width, index / width);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+        if changes.is_empty() {
+            return None;
+        }
+        Some(Rect::new(left, top, right - left, bottom - top))
+    }
+
+    /// Reverts the most recent step, returning the region it covered, or
+    /// `None` if there was nothing to undo. Does nothing inside a transaction.
+    pub fn undo(&mut self) -> Option<Rect> {
+        if self.depth > 0 {
+            return None;
+        }
+        let edit = self.undo.pop_back()?;
+        self.recorded -= edit.changes.len();
+        for (index, before, _) in edit.changes.iter().rev() {
+            self.stamp.cells[*index] = before.clone();
+        }
+        let region = self.bounds(&edit.changes);
+        self.redo.push(edit);
+        region
+    }
+
+    /// Reapplies the most recently undone step, returning the region it
+    /// covered, or `None` if there was nothing to redo. Does nothing inside a
+    /// transaction.
+    pub fn redo(&mut self) -> Option<Rect> {
+        if self.depth > 0 {
+            return None;
+        }
+        let edit = self.redo.pop()?;
+        for (index, _, after) in edit.changes.iter() {
+            self.stamp.cells[*index] = after.clone();
+        }
+        let region = self.bounds(&edit.changes);
+        self.recorded += edit.changes.len();
+        self.undo.push_back(edit);
+        self.trim();
+        region
+    }
+
+    pub fn set_at(&mut self, coord: (usize, usize), element: StampPart<T>) {
+        if let Err(err) = self.try_set_at(coord, element) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_set_at(
+        &mut self,
+        coord: (usize, usize),
+        element: StampPart<T>,
+    ) -> Result<(), StampError> {
+        let region = self.stamp.clip(coord.0 as isize, coord.1 as isize, 1, 1);
+        self.record(region, |stamp| stamp.try_set_at(coord, element))
+    }
+
+    pub fn stamp(&mut self, stamp: &Stamp<StampPart<T>>, pos_x: usize, pos_y: usize) {
+        if let Err(err) = self.try_stamp(stamp, pos_x, pos_y) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Records and performs `Stamp::try_stamp`.
+    pub fn try_stamp(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: usize,
+        pos_y: usize,
+    ) -> Result<(), StampError> {
+        let region = self.stamp.clip(
+            pos_x as isize,
+            pos_y as isize,
+            stamp.width(),
+            stamp.height(),
+        );
+        self.record(region, |target| target.try_stamp(stamp, pos_x, pos_y))
+    }
+
+    /// Records and performs `Stamp::stamp_clipped`.
+    pub fn stamp_clipped(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+    ) -> Option<Rect> {
+        let region = self.stamp.clip(pos_x, pos_y, stamp.width(), stamp.height());
+        self.record(region, |target| target.stamp_clipped(stamp, pos_x, pos_y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use StampPart::{Transparent, Use};
+
+    fn blank() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![vec![Use(0); 3]; 2])
+    }
+
+    #[test]
+    fn undo_and_redo_should_step_through_the_changes() {
+        let mut history = History::new(blank());
+        history.stamp(&Stamp::new(vec![vec![Use(1), Transparent, Use(2)]]), 0, 1);
+        history.set_at((1, 0), Use(3));
+        assert_eq!(history.undo(), Some(Rect::new(1, 0, 1, 1)));
+        assert_eq!(history.undo(), Some(Rect::new(0, 1, 3, 1)));
+        assert_eq!(history.current(), &blank());
+        assert_eq!(history.undo(), None);
+        history.redo();
+        let expected = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(1), Use(0), Use(2)],
+        ]);
+        assert_eq!(history.current(), &expected);
+        history.set_at((0, 0), Use(4));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn a_transaction_should_be_undone_as_one_step() {
+        let mut history = History::new(blank());
+        history.transaction(|history| {
+            history.set_at((0, 0), Use(1));
+            history.stamp_clipped(&Stamp::new(vec![vec![Use(2), Use(2)]]), 2, 1);
+            history.set_at((0, 0), Use(5));
+        });
+        history.undo();
+        assert_eq!(history.current(), &blank());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn undo_and_redo_should_do_nothing_inside_a_transaction() {
+        let mut history = History::new(blank());
+        history.set_at((0, 0), Use(1));
+        history.undo();
+        history.redo();
+        history.transaction(|history| {
+            history.set_at((0, 0), Use(2));
+            assert_eq!(history.undo(), None);
+            assert_eq!(history.redo(), None);
+        });
+        assert_eq!(history.current().get_at((0, 0)), Some(&Use(2)));
+        while history.undo().is_some() {}
+        assert_eq!(history.current(), &blank());
+    }
+
+    #[test]
+    fn clear_should_drop_changes_from_an_open_transaction() {
+        let mut history = History::new(blank());
+        history.transaction(|history| {
+            history.set_at((0, 0), Use(1));
+            history.clear();
+            history.set_at((1, 0), Use(1));
+        });
+        history.undo();
+        assert!(!history.can_undo());
+        assert_eq!(history.current().get_at((0, 0)), Some(&Use(1)));
+        assert_eq!(history.current().get_at((1, 0)), Some(&Use(0)));
+    }
+
+    #[test]
+    fn the_budget_should_forget_the_oldest_steps() {
+        let mut history = History::new(blank()).with_budget(2);
+        history.set_at((0, 0), Use(1));
+        history.set_at((1, 0), Use(1));
+        history.set_at((2, 0), Use(1));
+        history.undo();
+        history.undo();
+        assert!(!history.can_undo());
+        assert_eq!(history.current().get_at((0, 0)), Some(&Use(1)));
+    }
+
+    #[test]
+    fn failed_operations_should_not_be_recorded() {
+        let mut history = History::new(blank());
+        assert!(history.try_set_at((5, 5), Use(1)).is_err());
+        assert!(history
+            .try_stamp(&Stamp::new(vec![vec![Use(1); 4]]), 0, 0)
+            .is_err());
+        assert!(!history.can_undo());
+    }
+}
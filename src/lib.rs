@@ -2,30 +2,113 @@
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+mod approx;
+mod blend;
+mod boundary;
+mod capture;
+mod fit;
+mod history;
+mod mask;
+mod match_index;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod patch;
+#[cfg(feature = "rand")]
+mod program;
+mod query_set;
+mod replace;
+mod rule;
+mod select;
+mod transform;
+
+pub use approx::ApproxMatch;
+pub use blend::{BlendMode, Merge};
+pub use boundary::Boundary;
+use capture::CaptureMatcher;
+pub use capture::Captures;
+pub use history::History;
+pub use match_index::MatchIndex;
+pub use patch::{PatchRun, StampPatch};
+#[cfg(feature = "rand")]
+pub use program::{Mode, RuleProgram, RuleSet};
+pub use query_set::QuerySet;
+pub use replace::ReplaceStampPart;
+pub use rule::Rule;
+pub use select::Strategy;
+pub use transform::{Symmetry, Transform};
 
 pub type Pattern<T> = Vec<Vec<T>>;
 
-fn transpose<T>(v: &mut Vec<Vec<T>>) -> Vec<Vec<T>> {
-    let len = v[0].len();
-    let mut iters: Vec<_> = v.drain(0..).map(|n| n.into_iter()).collect();
-    (0..len)
-        .map(|_| {
-            iters
-                .iter_mut()
-                .map(|n| n.next().unwrap())
-                .collect::<Vec<T>>()
-        })
-        .collect()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StampError {
+    /// A row did not have the same length as the first row.
+    RaggedRows {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A coordinate fell outside of the stamp.
+    OutOfBounds { x: usize, y: usize },
+    /// The pattern had no rows or no columns.
+    EmptyPattern,
+    /// A replacement referred to a capture that was not bound.
+    UnboundCapture(char),
+    /// A stamp's cells did not fill its width and height exactly.
+    CellCount {
+        width: usize,
+        height: usize,
+        found: usize,
+    },
+    /// Two stamps that must be the same size were not.
+    SizeMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
 }
 
-fn reverse_rows<T>(pattern: &mut Pattern<T>) {
-    pattern.iter_mut().for_each(|row| row.reverse());
-}
-fn reverse_cols<T>(pattern: &mut Pattern<T>) {
-    pattern.reverse();
+impl fmt::Display for StampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StampError::RaggedRows {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} has length {} but expected {}",
+                row, found, expected
+            ),
+            StampError::OutOfBounds { x, y } => {
+                write!(f, "coordinate ({}, {}) is out of bounds", x, y)
+            }
+            StampError::EmptyPattern => write!(f, "pattern is empty"),
+            StampError::UnboundCapture(name) => write!(f, "capture '{}' is not bound", name),
+            StampError::CellCount {
+                width,
+                height,
+                found,
+            } => write!(
+                f,
+                "a {}x{} stamp cannot hold {} cells",
+                width, height, found
+            ),
+            StampError::SizeMismatch { expected, found } => write!(
+                f,
+                "stamp is {}x{} but expected {}x{}",
+                found.0, found.1, expected.0, expected.1
+            ),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl std::error::Error for StampError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum StampPart<T: Clone + PartialEq> {
     Use(T),
@@ -38,70 +121,362 @@ pub enum QueryStampPart<T: Clone + PartialEq> {
     Is(Box<[T]>),
     Not(Box<[T]>),
     Any,
+    /// Matches only positions outside of the stamp being searched. See
+    /// `Stamp::find_with_boundary`.
+    OutOfBounds,
+    /// Matches only `StampPart::Transparent` cells.
+    Transparent,
+    /// Matches any `StampPart::Use` cell, whatever its value.
+    Opaque,
+    /// Matches any cell and binds it to a name. Every capture cell with the
+    /// same name must hold the same cell.
+    Capture(char),
+    /// Matches a cell equal to the one bound by the `Capture` of that name.
+    SameAs(char),
+    /// Matches any used cell whose value passes the predicate.
+    ///
+    /// Closures can't be serialized, so with the `serde` feature serializing
+    /// anything that holds this cell, such as a `Rule` or `RuleProgram`,
+    /// returns an error rather than dropping the cell.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Matches(Predicate<T>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl<T: Clone + PartialEq> QueryStampPart<T> {
+    /// Whether `part`, a cell inside the stamp being searched, satisfies this
+    /// query cell. `Capture` and `SameAs` always match on their own, the
+    /// search checks their bindings across the whole query.
+    pub fn matches(&self, part: &StampPart<T>) -> bool {
+        match self {
+            QueryStampPart::Any | QueryStampPart::Capture(_) | QueryStampPart::SameAs(_) => true,
+            QueryStampPart::OutOfBounds => false,
+            QueryStampPart::Transparent => *part == StampPart::Transparent,
+            QueryStampPart::Opaque => *part != StampPart::Transparent,
+            QueryStampPart::Not(q) => match part {
+                StampPart::Use(tq) => !q.contains(tq),
+                StampPart::Transparent => true,
+            },
+            QueryStampPart::Is(q) => match part {
+                StampPart::Use(tq) => q.contains(tq),
+                StampPart::Transparent => false,
+            },
+            QueryStampPart::Matches(predicate) => match part {
+                StampPart::Use(tq) => predicate.test(tq),
+                StampPart::Transparent => false,
+            },
+        }
+    }
+}
+
+/// A shareable test on a cell value, for conditions that can't be written as
+/// a list of values.
+#[derive(Clone)]
+pub struct Predicate<T>(Arc<dyn Fn(&T) -> bool + Send + Sync>);
+
+impl<T> Predicate<T> {
+    pub fn new<F: Fn(&T) -> bool + Send + Sync + 'static>(predicate: F) -> Self {
+        Self(Arc::new(predicate))
+    }
+    pub fn test(&self, value: &T) -> bool {
+        (self.0)(value)
+    }
+}
+
+impl<T> fmt::Debug for Predicate<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Predicate({:p})", Arc::as_ptr(&self.0))
+    }
+}
+
+/// Predicates are equal only if they are clones of the same closure.
+impl<T> PartialEq for Predicate<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// An axis-aligned region of a stamp, in cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+    /// Whether the two regions share at least one cell.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+}
+
+/// A two-dimensional grid stored row-major in a single buffer.
+///
+/// With the `serde` feature a stamp is written as its `width`, `height` and
+/// row-major `cells`, and reading one fails if the number of cells doesn't
+/// match the dimensions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(try_from = "StampData<T>", bound(deserialize = "T: Deserialize<'de>"))
+)]
 pub struct Stamp<T: Clone + PartialEq> {
-    pub pattern: Pattern<T>,
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
 }
 
-impl<'a, T: Clone + PartialEq> Stamp<T> {
+/// The serialized form of a `Stamp`, checked before it becomes one.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct StampData<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T: Clone + PartialEq> TryFrom<StampData<T>> for Stamp<T> {
+    type Error = StampError;
+
+    fn try_from(data: StampData<T>) -> Result<Self, StampError> {
+        let expected = data.width.checked_mul(data.height);
+        if expected != Some(data.cells.len()) {
+            return Err(StampError::CellCount {
+                width: data.width,
+                height: data.height,
+                found: data.cells.len(),
+            });
+        }
+        Ok(Self {
+            width: data.width,
+            height: data.height,
+            cells: data.cells,
+        })
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<T> {
+    /// Builds a stamp from nested rows.
+    ///
+    /// Panics if the rows are not all the same length.
     pub fn new(pattern: Pattern<T>) -> Self {
-        Self { pattern }
+        match Self::from_rows(pattern) {
+            Ok(stamp) => stamp,
+            Err(err) => panic!("{}", err),
+        }
+    }
+    /// Builds a stamp from nested rows, rejecting ragged or empty patterns.
+    pub fn try_new(pattern: Pattern<T>) -> Result<Self, StampError> {
+        let stamp = Self::from_rows(pattern)?;
+        if stamp.cells.is_empty() {
+            return Err(StampError::EmptyPattern);
+        }
+        Ok(stamp)
+    }
+    fn from_rows(pattern: Pattern<T>) -> Result<Self, StampError> {
+        let height = pattern.len();
+        let width = pattern.first().map_or(0, |row| row.len());
+        if let Some((row, found)) = pattern
+            .iter()
+            .map(|row| row.len())
+            .enumerate()
+            .find(|(_, len)| *len != width)
+        {
+            return Err(StampError::RaggedRows {
+                row,
+                expected: width,
+                found,
+            });
+        }
+        let cells = pattern.into_iter().flatten().collect();
+        Ok(Self {
+            width,
+            height,
+            cells,
+        })
+    }
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+    /// The part of a `width` by `height` region at `(pos_x, pos_y)` that lies
+    /// inside the stamp, or `None` if they don't overlap.
+    pub(crate) fn clip(
+        &self,
+        pos_x: isize,
+        pos_y: isize,
+        width: usize,
+        height: usize,
+    ) -> Option<Rect> {
+        let left = pos_x.max(0);
+        let top = pos_y.max(0);
+        let right = pos_x
+            .saturating_add(width as isize)
+            .min(self.width as isize);
+        let bottom = pos_y
+            .saturating_add(height as isize)
+            .min(self.height as isize);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Rect::new(
+            left as usize,
+            top as usize,
+            (right - left) as usize,
+            (bottom - top) as usize,
+        ))
+    }
+    /// Whether a `width` by `height` region at `(pos_x, pos_y)` lies inside
+    /// the stamp, without overflowing for positions near `usize::MAX`.
+    fn fits(&self, pos_x: usize, pos_y: usize, width: usize, height: usize) -> bool {
+        matches!(pos_x.checked_add(width), Some(right) if right <= self.width)
+            && matches!(pos_y.checked_add(height), Some(bottom) if bottom <= self.height)
+    }
+    /// Rebuilds the stamp with the given dimensions, reading each new cell
+    /// from the old coordinate returned by `source`.
+    fn remap<F: Fn(usize, usize) -> (usize, usize)>(
+        &mut self,
+        width: usize,
+        height: usize,
+        source: F,
+    ) {
+        let mut cells = Vec::with_capacity(self.cells.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = source(x, y);
+                cells.push(self.cells[self.index(src_x, src_y)].clone());
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.cells = cells;
     }
     pub fn rotate_90(&mut self) {
-        let mut pattern = transpose(&mut self.pattern);
-        reverse_rows(&mut pattern);
-        self.pattern = pattern;
+        let height = self.height;
+        self.remap(self.height, self.width, |x, y| (y, height - 1 - x));
     }
     pub fn rotate_n90(&mut self) {
-        let mut pattern = transpose(&mut self.pattern);
-        reverse_cols(&mut pattern);
-        self.pattern = pattern;
+        let width = self.width;
+        self.remap(self.height, self.width, |x, y| (width - 1 - y, x));
     }
     pub fn rotate_180(&mut self) {
-        self.rotate_90();
-        self.rotate_90();
+        self.cells.reverse();
     }
     pub fn flip_horizontal(&mut self) {
-        reverse_rows(&mut self.pattern);
+        let width = self.width;
+        if width > 0 {
+            self.cells.chunks_mut(width).for_each(|row| row.reverse());
+        }
     }
     pub fn flip_vertical(&mut self) {
-        reverse_cols(&mut self.pattern);
+        let (width, height) = (self.width, self.height);
+        self.remap(width, height, |x, y| (x, height - 1 - y));
     }
     pub fn height(&self) -> usize {
-        self.pattern.len()
+        self.height
     }
     pub fn width(&self) -> usize {
-        match self.pattern.get(0) {
-            Some(row) => row.len(),
-            None => 0,
+        self.width
+    }
+    /// Returns a stamp of the same size with `f` applied to every cell.
+    pub fn map<U: Clone + PartialEq, F: FnMut(&T) -> U>(&self, f: F) -> Stamp<U> {
+        Stamp {
+            width: self.width,
+            height: self.height,
+            cells: self.cells.iter().map(f).collect(),
         }
     }
+    /// The cells of the stamp in row-major order.
+    pub fn as_slice(&self) -> &[T] {
+        &self.cells
+    }
+    /// Iterates over the rows of the stamp from top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.height).map(move |y| &self.cells[y * self.width..(y + 1) * self.width])
+    }
     pub fn set_at(&mut self, coord: (usize, usize), element: T) {
-        self.pattern[coord.1][coord.0] = element;
+        if let Err(err) = self.try_set_at(coord, element) {
+            panic!("{}", err);
+        }
+    }
+    pub fn try_set_at(&mut self, coord: (usize, usize), element: T) -> Result<(), StampError> {
+        if coord.0 >= self.width || coord.1 >= self.height {
+            return Err(StampError::OutOfBounds {
+                x: coord.0,
+                y: coord.1,
+            });
+        }
+        let index = self.index(coord.0, coord.1);
+        self.cells[index] = element;
+        Ok(())
     }
     pub fn get_at(&self, coord: (usize, usize)) -> Option<&T> {
-        if let Some(row) = self.pattern.get(coord.1) {
-            return row.get(coord.0);
+        if coord.0 < self.width && coord.1 < self.height {
+            return self.cells.get(self.index(coord.0, coord.1));
         }
         None
     }
 }
 
-impl<'a, T: Clone + PartialEq> Stamp<StampPart<T>> {
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
     pub fn stamp(&mut self, stamp: &Stamp<StampPart<T>>, pos_x: usize, pos_y: usize) {
-        let stamp_height = stamp.height();
-        let stamp_width = stamp.width();
-        for y in pos_y..pos_y + stamp_height {
-            for x in pos_x..pos_x + stamp_width {
-                let stamp_pattern_element = &stamp.pattern[y - pos_y][x - pos_x];
+        if let Err(err) = self.try_stamp(stamp, pos_x, pos_y) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Stamps `stamp` at the given position, failing without writing
+    /// anything if it does not fit inside this stamp.
+    pub fn try_stamp(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: usize,
+        pos_y: usize,
+    ) -> Result<(), StampError> {
+        if stamp.width() > 0
+            && stamp.height() > 0
+            && !self.fits(pos_x, pos_y, stamp.width(), stamp.height())
+        {
+            return Err(StampError::OutOfBounds {
+                x: pos_x.saturating_add(stamp.width() - 1),
+                y: pos_y.saturating_add(stamp.height() - 1),
+            });
+        }
+        for (stamp_y, row) in stamp.rows().enumerate() {
+            for (stamp_x, stamp_pattern_element) in row.iter().enumerate() {
                 if let StampPart::Use(_) = stamp_pattern_element {
-                    self.pattern[y][x] = stamp_pattern_element.clone();
+                    let index = self.index(pos_x + stamp_x, pos_y + stamp_y);
+                    self.cells[index] = stamp_pattern_element.clone();
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Stamps `stamp` with its top left corner at `(pos_x, pos_y)`, writing only
+    /// the cells that land inside this stamp. Returns the region that was
+    /// overlapped, or `None` if the stamp fell entirely outside.
+    pub fn stamp_clipped(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+    ) -> Option<Rect> {
+        self.stamp_transformed(stamp, pos_x, pos_y, Transform::Identity)
     }
 
     fn find_at_position(
@@ -110,47 +485,64 @@ impl<'a, T: Clone + PartialEq> Stamp<StampPart<T>> {
         pos_x: usize,
         pos_y: usize,
     ) -> bool {
-        for (query_y, this_y) in (pos_y..pos_y + query.height()).enumerate() {
-            if let Some(row) = self.pattern.get(this_y) {
-                for (query_x, this_x) in (pos_x..pos_x + query.width()).enumerate() {
-                    if let Some(col) = &row.get(this_x) {
-                        match &query.pattern[query_y][query_x] {
-                            QueryStampPart::Any => {}
-                            QueryStampPart::Not(q) => match col {
-                                StampPart::Use(tq) if q.contains(tq) => return false,
-                                _ => {}
-                            },
-                            QueryStampPart::Is(q) => match col {
-                                StampPart::Use(tq) if q.contains(tq) => {}
-                                _ => return false,
-                            },
-                        }
-                    } else {
-                        return false;
-                    }
-                }
-            } else {
-                return false;
-            }
+        self.captures_at_position(query, pos_x, pos_y).is_some()
+    }
+
+    /// Matches `query` at the given position, returning the values bound by
+    /// its capture cells if it matched.
+    pub(crate) fn captures_at_position(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        pos_x: usize,
+        pos_y: usize,
+    ) -> Option<Captures<T>> {
+        self.captures_at_position_transformed(query, Transform::Identity, pos_x, pos_y)
+    }
+
+    /// The range of origins along each axis at which `query` fits entirely
+    /// inside this stamp, as `(x_range, y_range)`.
+    fn origin_ranges(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+    ) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        self.origin_ranges_for(query.width(), query.height())
+    }
+
+    /// The range of origins along each axis at which a `width` by `height`
+    /// query fits entirely inside this stamp.
+    fn origin_ranges_for(
+        &self,
+        width: usize,
+        height: usize,
+    ) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        if height > self.height() || width > self.width() {
+            return (0..0, 0..0);
         }
-        true
+        (0..self.width() - width + 1, 0..self.height() - height + 1)
+    }
+
+    /// Lazily yields every position at which `query` matches, in row-major order.
+    pub fn find_iter<'a>(
+        &'a self,
+        query: &'a Stamp<QueryStampPart<T>>,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.find_iter_transformed(query, Transform::Identity)
     }
 
     pub fn find(&self, query: &Stamp<QueryStampPart<T>>) -> Vec<(usize, usize)> {
-        let mut matches = Vec::new();
-        if query.height() > self.height() || query.width() > self.width() {
-            return matches;
-        }
-        let last_y_index = self.height() - query.height();
-        let last_x_index = self.width() - query.width();
-        for y in 0..=last_y_index {
-            for x in 0..=last_x_index {
-                if self.find_at_position(query, x, y) {
-                    matches.push((x, y))
-                }
-            }
-        }
-        matches
+        self.find_iter(query).collect()
+    }
+
+    pub fn find_first(&self, query: &Stamp<QueryStampPart<T>>) -> Option<(usize, usize)> {
+        self.find_iter(query).next()
+    }
+
+    pub fn any_match(&self, query: &Stamp<QueryStampPart<T>>) -> bool {
+        self.find_first(query).is_some()
+    }
+
+    pub fn count_matches(&self, query: &Stamp<QueryStampPart<T>>) -> usize {
+        self.find_iter(query).count()
     }
 
     pub fn find_at_xy(&self, query: &Stamp<QueryStampPart<T>>, pos_x: usize, pos_y: usize) -> bool {
@@ -158,31 +550,19 @@ impl<'a, T: Clone + PartialEq> Stamp<StampPart<T>> {
     }
 
     pub fn find_at_y(&self, query: &Stamp<QueryStampPart<T>>, pos_y: usize) -> Vec<(usize, usize)> {
-        let mut matches = Vec::new();
-        if query.height() > self.height() || query.width() > self.width() {
-            return matches;
-        }
-        let last_x_index = self.width() - query.width();
-        for pos_x in 0..=last_x_index {
-            if self.find_at_position(query, pos_x, pos_y) {
-                matches.push((pos_x, pos_y))
-            }
-        }
-        matches
+        let (x_range, _) = self.origin_ranges(query);
+        x_range
+            .filter(|&pos_x| self.find_at_position(query, pos_x, pos_y))
+            .map(|pos_x| (pos_x, pos_y))
+            .collect()
     }
 
     pub fn find_at_x(&self, query: &Stamp<QueryStampPart<T>>, pos_x: usize) -> Vec<(usize, usize)> {
-        let mut matches = Vec::new();
-        if query.height() > self.height() || query.width() > self.width() {
-            return matches;
-        }
-        let last_y_index = self.height() - query.height();
-        for pos_y in 0..=last_y_index {
-            if self.find_at_position(query, pos_x, pos_y) {
-                matches.push((pos_x, pos_y))
-            }
-        }
-        matches
+        let (_, y_range) = self.origin_ranges(query);
+        y_range
+            .filter(|&pos_y| self.find_at_position(query, pos_x, pos_y))
+            .map(|pos_y| (pos_x, pos_y))
+            .collect()
     }
 }
 
@@ -191,6 +571,40 @@ mod tests {
     use super::*;
     use QueryStampPart::{Any, Is, Not};
     use StampPart::{Transparent, Use};
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_round_trip_through_serde() {
+        let stamp = Stamp::new(vec![vec![Use(1), Transparent], vec![Use(2), Use(3)]]);
+        let json = serde_json::to_string(&stamp).unwrap();
+        let result: Stamp<StampPart<i32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(result, stamp);
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_fail_to_serialize_a_predicate() {
+        let rule = Rule::new(
+            Stamp::new(vec![vec![
+                Is(Box::new([0])),
+                QueryStampPart::Matches(Predicate::new(|value: &i32| *value > 0)),
+            ]]),
+            Stamp::new(vec![vec![Use(1), Use(1)]]),
+        );
+        assert!(serde_json::to_string(&rule).is_err());
+        #[cfg(feature = "rand")]
+        {
+            let program = RuleProgram::new(vec![RuleSet::new(Mode::One, vec![rule])]);
+            assert!(serde_json::to_string(&program).is_err());
+        }
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn it_should_refuse_to_deserialize_cells_that_disagree_with_the_size() {
+        let json = r#"{"width":2,"height":2,"cells":[1,2,3]}"#;
+        let result = serde_json::from_str::<Stamp<i32>>(json);
+        assert!(result.is_err());
+        let json = r#"{"width":18446744073709551615,"height":2,"cells":[]}"#;
+        assert!(serde_json::from_str::<Stamp<i32>>(json).is_err());
+    }
     #[test]
     fn it_should_rotate_90() {
         let mut stamp = Stamp::new(vec![
@@ -355,6 +769,43 @@ mod tests {
         assert_eq!(result, expected);
     }
     #[test]
+    fn it_should_be_able_to_find_positions_using_predicates() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(4), Use(0)],
+            vec![Use(5), Transparent, Use(1)],
+        ]);
+        let above_three = QueryStampPart::Matches(Predicate::new(|height: &i32| *height > 3));
+        let query_stamp = Stamp::new(vec![vec![above_three]]);
+        let result = stamp.find(&query_stamp);
+        let expected = vec![(1, 0), (0, 1)];
+        assert_eq!(result, expected);
+    }
+    #[test]
+    fn it_should_be_able_to_find_transparent_cells() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Transparent, Use(0)],
+            vec![Transparent, Transparent, Use(1)],
+        ]);
+        let query_stamp = Stamp::new(vec![
+            vec![QueryStampPart::Transparent],
+            vec![QueryStampPart::Transparent],
+        ]);
+        let result = stamp.find(&query_stamp);
+        let expected = vec![(1, 0)];
+        assert_eq!(result, expected);
+    }
+    #[test]
+    fn it_should_be_able_to_find_opaque_cells() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Transparent, Use(0)],
+            vec![Transparent, Transparent, Use(1)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![QueryStampPart::Opaque]]);
+        let result = stamp.find(&query_stamp);
+        let expected = vec![(0, 0), (2, 0), (2, 1)];
+        assert_eq!(result, expected);
+    }
+    #[test]
     fn it_should_find_multiple_stamps() {
         let stamp = Stamp::new(vec![
             vec![Use(1), Use(1), Use(0)],
@@ -368,6 +819,30 @@ mod tests {
         assert_eq!(result, expected);
     }
     #[test]
+    fn find_iter_should_yield_matches_lazily_in_row_major_order() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(1), Use(0)],
+            vec![Use(1), Use(0), Use(1)],
+            vec![Use(0), Use(0), Use(1)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([0]))]]);
+        let mut matches = stamp.find_iter(&query_stamp);
+        assert_eq!(matches.next(), Some((2, 0)));
+        assert_eq!(matches.next(), Some((1, 1)));
+        assert_eq!(stamp.find_first(&query_stamp), Some((2, 0)));
+        assert_eq!(stamp.count_matches(&query_stamp), 7);
+        assert!(stamp.any_match(&query_stamp));
+    }
+    #[test]
+    fn find_iter_should_be_empty_if_query_is_larger_than_stamp() {
+        let stamp = Stamp::new(vec![vec![Use(0)]]);
+        let query_stamp = Stamp::new(vec![vec![Any, Any]]);
+        assert_eq!(stamp.find_first(&query_stamp), None);
+        assert!(!stamp.any_match(&query_stamp));
+        assert_eq!(stamp.count_matches(&query_stamp), 0);
+    }
+    #[test]
     fn it_should_stamp_in_the_top_left_corner() {
         let mut stamp = Stamp::new(vec![
             vec![Use(0), Use(0), Use(0)],
@@ -458,6 +933,43 @@ mod tests {
         assert_eq!(stamp, expected);
     }
     #[test]
+    fn stamp_clipped_should_write_only_the_overlapping_region() {
+        let mut stamp = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let replace_stamp = Stamp::new(vec![
+            vec![Use(1), Use(1), Use(1)],
+            vec![Use(1), Use(1), Transparent],
+            vec![Use(1), Use(1), Use(1)],
+        ]);
+        let result = stamp.stamp_clipped(&replace_stamp, -1, 1);
+        let expected = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(1), Use(1), Use(0)],
+            vec![Use(1), Use(0), Use(0)],
+        ]);
+        assert_eq!(stamp, expected);
+        assert_eq!(
+            result,
+            Some(Rect {
+                x: 0,
+                y: 1,
+                width: 2,
+                height: 2
+            })
+        );
+    }
+    #[test]
+    fn stamp_clipped_should_return_none_if_nothing_overlaps() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0)], vec![Use(0), Use(0)]]);
+        let replace_stamp = Stamp::new(vec![vec![Use(1)]]);
+        assert_eq!(stamp.stamp_clipped(&replace_stamp, 2, 0), None);
+        assert_eq!(stamp.stamp_clipped(&replace_stamp, 0, -1), None);
+        assert!(stamp.as_slice().iter().all(|part| *part == Use(0)));
+    }
+    #[test]
     fn it_should_return_none_if_getting_coord_out_of_bound() {
         let stamp = Stamp::new(vec![
             vec![Use(0), Use(0), Use(0)],
@@ -481,7 +993,61 @@ mod tests {
         let expected = Some(&Use(2));
         assert_eq!(result, expected);
     }
-    fn it_should_error_if_setting_at_coord_out_of_bounds() {}
+    #[test]
+    fn it_should_error_if_setting_at_coord_out_of_bounds() {
+        let mut stamp = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let result = stamp.try_set_at((3, 1), Use(2));
+        let expected = Err(StampError::OutOfBounds { x: 3, y: 1 });
+        assert_eq!(result, expected);
+    }
+    #[test]
+    fn try_new_should_error_if_rows_have_different_lengths() {
+        let result = Stamp::try_new(vec![vec![Use(0), Use(1)], vec![Use(2)]]);
+        let expected = Err(StampError::RaggedRows {
+            row: 1,
+            expected: 2,
+            found: 1,
+        });
+        assert_eq!(result, expected);
+    }
+    #[test]
+    fn try_new_should_error_if_pattern_is_empty() {
+        let result: Result<Stamp<StampPart<i32>>, _> = Stamp::try_new(vec![]);
+        assert_eq!(result, Err(StampError::EmptyPattern));
+    }
+    #[test]
+    fn try_stamp_should_error_without_writing_if_stamp_overflows() {
+        let mut stamp = Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let replace_stamp = Stamp::new(vec![vec![Use(1), Use(1)], vec![Use(1), Use(1)]]);
+        let result = stamp.try_stamp(&replace_stamp, 2, 0);
+        assert_eq!(result, Err(StampError::OutOfBounds { x: 3, y: 1 }));
+        assert!(stamp.as_slice().iter().all(|part| *part == Use(0)));
+    }
+    #[test]
+    fn try_stamp_should_error_at_huge_positions() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0)]]);
+        let replace_stamp = Stamp::new(vec![vec![Use(1), Use(1)]]);
+        let result = stamp.try_stamp(&replace_stamp, usize::MAX, 0);
+        assert_eq!(
+            result,
+            Err(StampError::OutOfBounds {
+                x: usize::MAX,
+                y: 0
+            })
+        );
+        let query_stamp = Stamp::new(vec![vec![QueryStampPart::Any]]);
+        assert!(!stamp.find_at_xy(&query_stamp, usize::MAX, usize::MAX));
+        assert_eq!(
+            stamp.score_at(&query_stamp, usize::MAX, 0, |_, _| 0.0),
+            None
+        );
+    }
     #[test]
     fn it_should_be_able_to_set_ok_if_coord_in_bounds() {
         let mut stamp = Stamp::new(vec![
@@ -528,6 +1094,22 @@ mod tests {
         assert_eq!(result, expected);
     }
     #[test]
+    fn it_should_store_cells_in_row_major_order() {
+        let stamp = Stamp::new(vec![vec![Use(0), Use(1)], vec![Use(2), Use(3)]]);
+        assert_eq!(stamp.as_slice(), &[Use(0), Use(1), Use(2), Use(3)]);
+    }
+    #[test]
+    fn it_should_iterate_over_rows() {
+        let stamp = Stamp::new(vec![vec![Use(0), Use(1)], vec![Use(2), Use(3)]]);
+        let rows: Vec<&[StampPart<i32>]> = stamp.rows().collect();
+        assert_eq!(rows, vec![&[Use(0), Use(1)][..], &[Use(2), Use(3)][..]]);
+    }
+    #[test]
+    #[should_panic]
+    fn it_should_panic_if_rows_have_different_lengths() {
+        Stamp::new(vec![vec![Use(0), Use(1)], vec![Use(2)]]);
+    }
+    #[test]
     fn it_should_flip_horizontally() {
         let mut stamp = Stamp::new(vec![
             vec![Use(0), Use(0), Use(0)],
@@ -1,5 +1,4 @@
-mod lib;
-use lib::{Stamp, StampPart::Use, QueryStampPart::Is};
+use stamp_rs::{QueryStampPart::Is, Stamp, StampPart::Use};
 
 fn main() {
     let mut stamp = Stamp::new(vec![
@@ -0,0 +1,94 @@
+use crate::{QueryStampPart, Rect, Stamp, StampError, StampPart};
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Stamps `stamp` with its top left corner at `(pos_x, pos_y)`, writing
+    /// each `Use` cell only if the cell beneath it matches the cell of `mask`
+    /// at the same place. Each cell is checked on its own with
+    /// `QueryStampPart::matches`, so `Capture` and `SameAs` cells always pass.
+    ///
+    /// Cells that land outside of this stamp are clipped as with
+    /// `stamp_clipped`, and the overlapped region is returned. Fails without
+    /// writing anything if `mask` is not the same size as `stamp`.
+    pub fn stamp_masked(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        mask: &Stamp<QueryStampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+    ) -> Result<Option<Rect>, StampError> {
+        if (mask.width(), mask.height()) != (stamp.width(), stamp.height()) {
+            return Err(StampError::SizeMismatch {
+                expected: (stamp.width(), stamp.height()),
+                found: (mask.width(), mask.height()),
+            });
+        }
+        let region = match self.clip(pos_x, pos_y, stamp.width(), stamp.height()) {
+            Some(region) => region,
+            None => return Ok(None),
+        };
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let stamp_index =
+                    stamp.index((x as isize - pos_x) as usize, (y as isize - pos_y) as usize);
+                let stamp_pattern_element = &stamp.cells[stamp_index];
+                let index = self.index(x, y);
+                if let StampPart::Use(_) = stamp_pattern_element {
+                    if mask.cells[stamp_index].matches(&self.cells[index]) {
+                        self.cells[index] = stamp_pattern_element.clone();
+                    }
+                }
+            }
+        }
+        Ok(Some(region))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Any, Is, Not};
+    use StampPart::{Transparent, Use};
+
+    const GRASS: i32 = 1;
+
+    #[test]
+    fn it_should_only_write_where_the_mask_matches() {
+        let mut stamp = Stamp::new(vec![
+            vec![Use(GRASS), Use(0), Use(GRASS)],
+            vec![Use(GRASS), Use(GRASS), Transparent],
+        ]);
+        let brush = Stamp::new(vec![vec![Use(7); 3]; 2]);
+        let mask = Stamp::new(vec![vec![Is(Box::new([GRASS])); 3]; 2]);
+        let result = stamp.stamp_masked(&brush, &mask, 0, 0);
+        assert_eq!(result, Ok(Some(Rect::new(0, 0, 3, 2))));
+        let expected = Stamp::new(vec![
+            vec![Use(7), Use(0), Use(7)],
+            vec![Use(7), Use(7), Transparent],
+        ]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn each_cell_should_use_its_own_mask_cell() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0), Use(0)]]);
+        let brush = Stamp::new(vec![vec![Use(5), Transparent, Use(5)]]);
+        let mask = Stamp::new(vec![vec![Not(Box::new([0])), Any, Any]]);
+        stamp.stamp_masked(&brush, &mask, -1, 0).unwrap();
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(0), Use(5), Use(0)]]));
+    }
+
+    #[test]
+    fn it_should_reject_a_mask_of_another_size() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0)]]);
+        let brush = Stamp::new(vec![vec![Use(5), Use(5)]]);
+        let mask = Stamp::new(vec![vec![Any]]);
+        let result = stamp.stamp_masked(&brush, &mask, 0, 0);
+        assert_eq!(
+            result,
+            Err(StampError::SizeMismatch {
+                expected: (2, 1),
+                found: (1, 1),
+            })
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeSet;
+
+use crate::{QuerySet, QueryStampPart, Rect, Stamp, StampPart};
+
+/// The matches of a set of queries against one stamp, kept up to date as the
+/// stamp changes by re-checking only the origins near each change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchIndex<T: Clone + PartialEq> {
+    queries: Vec<Stamp<QueryStampPart<T>>>,
+    /// The origins of each query's matches, keyed `(y, x)` so that they
+    /// iterate in row-major order.
+    matches: Vec<BTreeSet<(usize, usize)>>,
+}
+
+impl<T: Clone + PartialEq> MatchIndex<T> {
+    /// Indexes every match of `queries` in `stamp`.
+    pub fn new(queries: Vec<Stamp<QueryStampPart<T>>>, stamp: &Stamp<StampPart<T>>) -> Self {
+        let query_set = QuerySet::new(queries);
+        let mut matches = vec![BTreeSet::new(); query_set.len()];
+        for (index, (x, y)) in query_set.find_all(stamp) {
+            matches[index].insert((y, x));
+        }
+        Self {
+            queries: query_set.queries().to_vec(),
+            matches,
+        }
+    }
+
+    pub fn queries(&self) -> &[Stamp<QueryStampPart<T>>] {
+        &self.queries
+    }
+
+    /// The current matches of the query at `index`, in row-major order.
+    pub fn matches(&self, index: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.matches[index].iter().map(|&(y, x)| (x, y))
+    }
+
+    pub fn count(&self, index: usize) -> usize {
+        self.matches[index].len()
+    }
+
+    /// Re-checks every origin at which a query would overlap `dirty`, which
+    /// must cover every cell of `stamp` that changed since the last update.
+    pub fn update(&mut self, stamp: &Stamp<StampPart<T>>, dirty: Rect) {
+        if dirty.width == 0 || dirty.height == 0 {
+            return;
+        }
+        for (query, matches) in self.queries.iter().zip(self.matches.iter_mut()) {
+            let (x_range, y_range) = stamp.origin_ranges(query);
+            let first_x = dirty
+                .x
+                .saturating_add(1)
+                .saturating_sub(query.width())
+                .max(x_range.start);
+            let first_y = dirty
+                .y
+                .saturating_add(1)
+                .saturating_sub(query.height())
+                .max(y_range.start);
+            let end_x = dirty.x.saturating_add(dirty.width).min(x_range.end);
+            let end_y = dirty.y.saturating_add(dirty.height).min(y_range.end);
+            for y in first_y..end_y {
+                for x in first_x..end_x {
+                    if stamp.find_at_xy(query, x, y) {
+                        matches.insert((y, x));
+                    } else {
+                        matches.remove(&(y, x));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stamps `source` onto `stamp` as `Stamp::stamp_clipped` does and
+    /// updates the index for the region it covered.
+    pub fn stamp(
+        &mut self,
+        stamp: &mut Stamp<StampPart<T>>,
+        source: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+    ) -> Option<Rect> {
+        let dirty = stamp.stamp_clipped(source, pos_x, pos_y);
+        if let Some(dirty) = dirty {
+            self.update(stamp, dirty);
+        }
+        dirty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use QueryStampPart::{Any, Is};
+    use StampPart::{Transparent, Use};
+
+    #[test]
+    fn it_should_index_the_initial_matches() {
+        let stamp = Stamp::new(vec![
+            vec![Use(0), Use(1), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([0]))], vec![Is(Box::new([0]))]]);
+        let index = MatchIndex::new(vec![query_stamp.clone()], &stamp);
+        assert_eq!(
+            index.matches(0).collect::<Vec<_>>(),
+            stamp.find(&query_stamp)
+        );
+        assert_eq!(index.count(0), 2);
+    }
+
+    #[test]
+    fn update_should_ignore_dirty_regions_far_past_the_stamp() {
+        let stamp = Stamp::new(vec![vec![Use(0), Use(0)]]);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([0]))]]);
+        let mut index = MatchIndex::new(vec![query_stamp], &stamp);
+        index.update(&stamp, Rect::new(usize::MAX, 0, 1, 1));
+        index.update(&stamp, Rect::new(1, usize::MAX, usize::MAX, usize::MAX));
+        assert_eq!(index.count(0), 2);
+    }
+
+    #[test]
+    fn it_should_stay_in_step_with_find_after_stamping() {
+        let mut rng = StdRng::seed_from_u64(16);
+        let mut stamp = Stamp::new(vec![vec![Use(0); 12]; 10]);
+        let queries = vec![
+            Stamp::new(vec![vec![Is(Box::new([0])), Is(Box::new([1]))]]),
+            Stamp::new(vec![
+                vec![Is(Box::new([1]))],
+                vec![Any],
+                vec![Is(Box::new([2]))],
+            ]),
+            Stamp::new(vec![vec![Is(Box::new([0])); 3]; 3]),
+        ];
+        let mut index = MatchIndex::new(queries.clone(), &stamp);
+        for _ in 0..50 {
+            let brush = Stamp::new(vec![
+                vec![Use(rng.gen_range(0..3)), Transparent],
+                vec![Use(rng.gen_range(0..3)), Use(rng.gen_range(0..3))],
+            ]);
+            let (x, y) = (rng.gen_range(-1..12), rng.gen_range(-1..10));
+            index.stamp(&mut stamp, &brush, x, y);
+            for (query_index, query) in queries.iter().enumerate() {
+                let indexed: Vec<(usize, usize)> = index.matches(query_index).collect();
+                assert_eq!(indexed, stamp.find(query));
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use rayon::prelude::*;
+
+use crate::{QueryStampPart, Stamp, StampPart};
+
+impl<T: Clone + PartialEq + Send + Sync> Stamp<StampPart<T>> {
+    /// Finds `query` like `find`, splitting the rows of origins across
+    /// threads. Matches are returned in the same row-major order as `find`.
+    pub fn par_find(&self, query: &Stamp<QueryStampPart<T>>) -> Vec<(usize, usize)> {
+        let (x_range, y_range) = self.origin_ranges(query);
+        y_range
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                x_range
+                    .clone()
+                    .filter(move |&x| self.find_at_position(query, x, y))
+                    .map(move |x| (x, y))
+            })
+            .collect()
+    }
+
+    /// Counts the matches of `query` like `count_matches`, splitting the rows
+    /// of origins across threads.
+    pub fn par_count(&self, query: &Stamp<QueryStampPart<T>>) -> usize {
+        let (x_range, y_range) = self.origin_ranges(query);
+        y_range
+            .into_par_iter()
+            .map(|y| {
+                x_range
+                    .clone()
+                    .filter(|&x| self.find_at_position(query, x, y))
+                    .count()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Any, Is};
+    use StampPart::Use;
+
+    fn stamp() -> Stamp<StampPart<usize>> {
+        Stamp::new(
+            (0..64)
+                .map(|y| (0..48).map(|x| Use((x * 7 + y * 3) % 5)).collect())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn par_find_should_return_the_same_matches_in_the_same_order_as_find() {
+        let query_stamp = Stamp::new(vec![
+            vec![Is(Box::new([0])), Any],
+            vec![Any, Is(Box::new([0, 1]))],
+        ]);
+        let expected = stamp().find(&query_stamp);
+        assert!(!expected.is_empty());
+        assert_eq!(stamp().par_find(&query_stamp), expected);
+    }
+
+    #[test]
+    fn par_count_should_agree_with_count_matches() {
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([2]))], vec![Is(Box::new([0, 4]))]]);
+        assert_eq!(
+            stamp().par_count(&query_stamp),
+            stamp().count_matches(&query_stamp)
+        );
+    }
+
+    #[test]
+    fn it_should_find_nothing_if_the_query_does_not_fit() {
+        let query_stamp = Stamp::new(vec![vec![Any; 49]]);
+        assert!(stamp().par_find(&query_stamp).is_empty());
+        assert_eq!(stamp().par_count(&query_stamp), 0);
+    }
+}
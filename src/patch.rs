@@ -0,0 +1,174 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Stamp, StampError};
+
+/// A horizontal run of changed cells starting at `(x, y)`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PatchRun<T: Clone + PartialEq> {
+    pub x: usize,
+    pub y: usize,
+    pub cells: Vec<T>,
+}
+
+/// The cells that changed between two stamps of the same size, grouped into
+/// runs along each row in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StampPatch<T: Clone + PartialEq> {
+    pub width: usize,
+    pub height: usize,
+    pub runs: Vec<PatchRun<T>>,
+}
+
+impl<T: Clone + PartialEq> StampPatch<T> {
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The number of cells the patch writes.
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|run| run.cells.len()).sum()
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<T> {
+    /// The patch that turns this stamp into `other`, failing if the stamps
+    /// are not the same size.
+    pub fn diff(&self, other: &Stamp<T>) -> Result<StampPatch<T>, StampError> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(StampError::SizeMismatch {
+                expected: (self.width, self.height),
+                found: (other.width, other.height),
+            });
+        }
+        let mut runs: Vec<PatchRun<T>> = Vec::new();
+        for (index, (before, after)) in self.cells.iter().zip(other.cells.iter()).enumerate() {
+            if before == after {
+                continue;
+            }
+            let (x, y) = (index % self.width, index / self.width);
+            match runs.last_mut() {
+                Some(run) if run.y == y && run.x + run.cells.len() == x => {
+                    run.cells.push(after.clone())
+                }
+                _ => runs.push(PatchRun {
+                    x,
+                    y,
+                    cells: vec![after.clone()],
+                }),
+            }
+        }
+        Ok(StampPatch {
+            width: self.width,
+            height: self.height,
+            runs,
+        })
+    }
+
+    /// Writes every run of `patch`, failing without writing anything if the
+    /// patch was made for a stamp of another size.
+    pub fn apply_patch(&mut self, patch: &StampPatch<T>) -> Result<(), StampError> {
+        if (self.width, self.height) != (patch.width, patch.height) {
+            return Err(StampError::SizeMismatch {
+                expected: (patch.width, patch.height),
+                found: (self.width, self.height),
+            });
+        }
+        for run in patch.runs.iter() {
+            let fits = matches!(run.x.checked_add(run.cells.len()), Some(end) if end <= self.width);
+            if run.y >= self.height || !fits {
+                return Err(StampError::OutOfBounds {
+                    x: run.x.saturating_add(run.cells.len().max(1) - 1),
+                    y: run.y,
+                });
+            }
+        }
+        for run in patch.runs.iter() {
+            let start = self.index(run.x, run.y);
+            self.cells[start..start + run.cells.len()].clone_from_slice(&run.cells);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StampPart::{self, Transparent, Use};
+
+    fn before() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ])
+    }
+
+    fn after() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(0), Use(1), Use(2)],
+            vec![Transparent, Use(0), Use(3)],
+        ])
+    }
+
+    #[test]
+    fn diff_should_group_changed_cells_into_runs() {
+        let patch = before().diff(&after()).unwrap();
+        let expected = vec![
+            PatchRun {
+                x: 1,
+                y: 0,
+                cells: vec![Use(1), Use(2)],
+            },
+            PatchRun {
+                x: 0,
+                y: 1,
+                cells: vec![Transparent],
+            },
+            PatchRun {
+                x: 2,
+                y: 1,
+                cells: vec![Use(3)],
+            },
+        ];
+        assert_eq!(patch.runs, expected);
+        assert_eq!(patch.len(), 4);
+        assert!(after().diff(&after()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn apply_patch_should_reproduce_the_other_stamp() {
+        let mut stamp = before();
+        stamp
+            .apply_patch(&before().diff(&after()).unwrap())
+            .unwrap();
+        assert_eq!(stamp, after());
+    }
+
+    #[test]
+    fn apply_patch_should_reject_a_patch_for_another_size() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 3]]);
+        let result = stamp.apply_patch(&before().diff(&after()).unwrap());
+        assert_eq!(
+            result,
+            Err(StampError::SizeMismatch {
+                expected: (3, 2),
+                found: (3, 1),
+            })
+        );
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(0); 3]]));
+    }
+
+    #[test]
+    fn diff_should_reject_a_stamp_of_another_size() {
+        let result = before().diff(&Stamp::new(vec![vec![Use(0); 2]]));
+        assert_eq!(
+            result,
+            Err(StampError::SizeMismatch {
+                expected: (3, 2),
+                found: (2, 1),
+            })
+        );
+    }
+}
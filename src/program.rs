@@ -0,0 +1,296 @@
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Captures, Rect, Rule, Stamp, StampPart};
+
+/// How a `RuleSet` rewrites the stamp on each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Mode {
+    /// Rewrite a single match, chosen at random from the matches of every
+    /// rule. Matches that would change nothing are passed over for another.
+    One,
+    /// Rewrite as many matches as possible without any of them overlapping,
+    /// visiting the matches of every rule in random order.
+    All,
+    /// Rewrite every match found against the state at the start of the step,
+    /// overlapping or not. Replacements read `Keep` and `FromOffset` cells
+    /// from that state too, so the order of the matches doesn't matter except
+    /// that later rules win where replacements overlap.
+    Parallel,
+}
+
+/// A group of rules applied together until a step changes nothing or the
+/// step limit is reached.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RuleSet<T: Clone + PartialEq> {
+    pub mode: Mode,
+    pub rules: Vec<Rule<T>>,
+    pub steps: Option<usize>,
+}
+
+impl<T: Clone + PartialEq> RuleSet<T> {
+    pub fn new(mode: Mode, rules: Vec<Rule<T>>) -> Self {
+        Self {
+            mode,
+            rules,
+            steps: None,
+        }
+    }
+
+    /// Limits the rule set to at most `steps` steps.
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// Every match of every rule as `(rule_index, (x, y), captures)`.
+    fn matches(&self, stamp: &Stamp<StampPart<T>>) -> Vec<(usize, (usize, usize), Captures<T>)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .flat_map(|(index, rule)| {
+                stamp
+                    .find_captures(&rule.query)
+                    .into_iter()
+                    .map(move |(position, captures)| (index, position, captures))
+            })
+            .collect()
+    }
+
+    /// Performs one step, returning false if no cell changed.
+    fn step<R: Rng + ?Sized>(&self, stamp: &mut Stamp<StampPart<T>>, rng: &mut R) -> bool {
+        let mut matches = self.matches(stamp);
+        if matches.is_empty() {
+            return false;
+        }
+        let mut rewritten = false;
+        match self.mode {
+            Mode::One => {
+                while !matches.is_empty() && !rewritten {
+                    let (index, (x, y), captures) =
+                        matches.swap_remove(rng.gen_range(0..matches.len()));
+                    rewritten = stamp.apply_at(&self.rules[index], x, y, &captures);
+                }
+            }
+            Mode::All => {
+                matches.shuffle(rng);
+                let mut chosen: Vec<Rect> = Vec::new();
+                for (index, (x, y), captures) in matches {
+                    let rule = &self.rules[index];
+                    let footprint = rule.footprint(x, y);
+                    if chosen.iter().all(|other| !other.intersects(&footprint)) {
+                        chosen.push(footprint);
+                        rewritten |= stamp.apply_at(rule, x, y, &captures);
+                    }
+                }
+            }
+            Mode::Parallel => {
+                let mut writes = Vec::new();
+                for (index, (x, y), captures) in matches {
+                    let replacement = &self.rules[index].replacement;
+                    if let Ok((_, match_writes)) =
+                        stamp.replacement_writes(replacement, x as isize, y as isize, &captures)
+                    {
+                        writes.extend(match_writes);
+                    }
+                }
+                let before: Vec<StampPart<T>> = writes
+                    .iter()
+                    .map(|(index, _)| stamp.cells[*index].clone())
+                    .collect();
+                let indices: Vec<usize> = writes.iter().map(|(index, _)| *index).collect();
+                stamp.write_cells(writes);
+                rewritten = indices
+                    .into_iter()
+                    .zip(before)
+                    .any(|(index, before)| stamp.cells[index] != before);
+            }
+        }
+        rewritten
+    }
+}
+
+/// An ordered list of rule sets, run one after another in the style of
+/// MarkovJunior.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RuleProgram<T: Clone + PartialEq> {
+    pub rule_sets: Vec<RuleSet<T>>,
+}
+
+impl<T: Clone + PartialEq> RuleProgram<T> {
+    pub fn new(rule_sets: Vec<RuleSet<T>>) -> Self {
+        Self { rule_sets }
+    }
+
+    /// Runs every rule set in order against `stamp`, moving on to the next
+    /// once a step of the current set changes nothing or its step limit is
+    /// reached. Returns the total number of steps taken.
+    ///
+    /// Choices are drawn from ChaCha8 seeded with `seed`, whose output is the
+    /// same on every platform, so a seed always produces the same result.
+    pub fn run(&self, stamp: &mut Stamp<StampPart<T>>, seed: u64) -> usize {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut total = 0;
+        for rule_set in self.rule_sets.iter() {
+            let mut steps = 0;
+            while !matches!(rule_set.steps, Some(limit) if steps >= limit) {
+                if !rule_set.step(stamp, &mut rng) {
+                    break;
+                }
+                steps += 1;
+            }
+            total += steps;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryStampPart::Is;
+    use crate::ReplaceStampPart;
+    use StampPart::Use;
+
+    fn rule(from: i32, to: i32) -> Rule<i32> {
+        Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([from]))]]),
+            Stamp::new(vec![vec![Use(to)]]),
+        )
+    }
+
+    fn count(stamp: &Stamp<StampPart<i32>>, value: i32) -> usize {
+        stamp
+            .as_slice()
+            .iter()
+            .filter(|part| **part == Use(value))
+            .count()
+    }
+
+    #[test]
+    fn one_mode_should_rewrite_a_single_match_per_step() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 4]; 3]);
+        let program =
+            RuleProgram::new(vec![RuleSet::new(Mode::One, vec![rule(0, 1)]).with_steps(5)]);
+        assert_eq!(program.run(&mut stamp, 1), 5);
+        assert_eq!(count(&stamp, 1), 5);
+    }
+
+    #[test]
+    fn all_mode_should_rewrite_non_overlapping_matches_in_one_step() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 4]; 3]);
+        let program =
+            RuleProgram::new(vec![RuleSet::new(Mode::All, vec![rule(0, 1)]).with_steps(1)]);
+        assert_eq!(program.run(&mut stamp, 1), 1);
+        assert_eq!(count(&stamp, 1), 12);
+    }
+
+    #[test]
+    fn parallel_mode_should_match_against_the_state_before_the_step() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(1), Use(2)]]);
+        let program = RuleProgram::new(vec![RuleSet::new(
+            Mode::Parallel,
+            vec![rule(0, 1), rule(1, 2)],
+        )
+        .with_steps(1)]);
+        program.run(&mut stamp, 1);
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(2), Use(2)]]));
+    }
+
+    #[test]
+    fn parallel_mode_should_resolve_offsets_against_the_state_before_the_step() {
+        let mut stamp = Stamp::new(vec![vec![Use(1), Use(0), Use(0)]]);
+        let shift_right = Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![ReplaceStampPart::FromOffset(-1, 0)]]),
+        );
+        let program = RuleProgram::new(vec![
+            RuleSet::new(Mode::Parallel, vec![shift_right]).with_steps(1)
+        ]);
+        program.run(&mut stamp, 1);
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(1), Use(0)]]));
+    }
+
+    #[test]
+    fn one_mode_should_pass_over_matches_that_cannot_be_rewritten() {
+        let stamp = Stamp::new(vec![vec![Use(0); 4]]);
+        let unbound = Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![ReplaceStampPart::FromCapture('a')]]),
+        );
+        let program = RuleProgram::new(vec![RuleSet::new(
+            Mode::One,
+            vec![unbound, rule(0, 0), rule(0, 1)],
+        )]);
+        for seed in 0..8 {
+            let mut stamp = stamp.clone();
+            assert_eq!(program.run(&mut stamp, seed), 4);
+            assert_eq!(count(&stamp, 1), 4);
+        }
+    }
+
+    #[test]
+    fn it_should_run_each_rule_set_until_nothing_matches() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 3]; 3]);
+        let program = RuleProgram::new(vec![
+            RuleSet::new(Mode::One, vec![rule(0, 1)]),
+            RuleSet::new(Mode::One, vec![rule(1, 2)]),
+        ]);
+        assert_eq!(program.run(&mut stamp, 1), 18);
+        assert_eq!(count(&stamp, 2), 9);
+    }
+
+    #[test]
+    fn it_should_stop_when_a_step_changes_nothing() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 3]; 3]);
+        let keep = Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![StampPart::Transparent]]),
+        );
+        let program = RuleProgram::new(vec![
+            RuleSet::new(Mode::One, vec![rule(0, 0)]),
+            RuleSet::new(Mode::All, vec![rule(0, 0), keep.clone()]),
+            RuleSet::new(Mode::Parallel, vec![keep]),
+        ]);
+        assert_eq!(program.run(&mut stamp, 1), 0);
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(0); 3]; 3]));
+    }
+
+    #[test]
+    fn it_should_produce_the_same_result_for_a_seed_on_every_platform() {
+        let mut stamp = Stamp::new(vec![vec![Use(0); 4]; 2]);
+        let program =
+            RuleProgram::new(vec![RuleSet::new(Mode::One, vec![rule(0, 1)]).with_steps(3)]);
+        program.run(&mut stamp, 7);
+        let expected = Stamp::new(vec![
+            vec![Use(0), Use(1), Use(1), Use(0)],
+            vec![Use(0), Use(0), Use(1), Use(0)],
+        ]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn it_should_be_reproducible_for_a_seed() {
+        let start = Stamp::new(vec![vec![Use(0); 6]; 6]);
+        let program = RuleProgram::new(vec![RuleSet::new(
+            Mode::One,
+            vec![Rule::new(
+                Stamp::new(vec![vec![Is(Box::new([0])), Is(Box::new([0]))]]),
+                Stamp::new(vec![vec![Use(1), Use(2)]]),
+            )],
+        )
+        .with_steps(4)]);
+        let run = |seed| {
+            let mut stamp = start.clone();
+            program.run(&mut stamp, seed);
+            stamp
+        };
+        assert_eq!(run(42), run(42));
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{QueryStampPart, Stamp, StampPart};
+
+/// A fixed-length bit vector used as Shift-And state.
+#[derive(Debug, Clone, PartialEq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(len: usize) -> Self {
+        Bits(vec![0; len.div_ceil(64)])
+    }
+    fn set(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+    fn clear(&mut self) {
+        self.0.iter_mut().for_each(|word| *word = 0);
+    }
+    fn or_assign(&mut self, other: &Bits) {
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word |= other;
+        }
+    }
+    /// One Shift-And step: `self = ((self << 1) | starts) & mask`.
+    fn step(&mut self, starts: &Bits, mask: &Bits) {
+        let mut carry = 0;
+        for ((word, start), mask) in self.0.iter_mut().zip(starts.0.iter()).zip(mask.0.iter()) {
+            let next_carry = *word >> 63;
+            *word = ((*word << 1) | carry | start) & mask;
+            carry = next_carry;
+        }
+    }
+    /// The indices of the bits set in both `self` and `other`.
+    fn common_ones<'a>(&'a self, other: &'a Bits) -> impl Iterator<Item = usize> + 'a {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .enumerate()
+            .flat_map(|(index, (a, b))| {
+                let mut word = a & b;
+                std::iter::from_fn(move || {
+                    if word == 0 {
+                        return None;
+                    }
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    Some(index * 64 + bit)
+                })
+            })
+    }
+}
+
+/// A bank of Shift-And patterns laid out end to end in one bit vector.
+#[derive(Debug, Clone, PartialEq)]
+struct ShiftAnd {
+    len: usize,
+    starts: Bits,
+    ends: Bits,
+    /// For each end bit, the pattern that finishes there.
+    patterns: Vec<usize>,
+}
+
+impl ShiftAnd {
+    /// Lays out patterns of the given lengths, returning the bank and the
+    /// first bit of each pattern.
+    fn new(lengths: &[usize]) -> (Self, Vec<usize>) {
+        let len = lengths.iter().sum();
+        let mut starts = Bits::new(len);
+        let mut ends = Bits::new(len);
+        let mut patterns = vec![usize::MAX; len];
+        let mut offsets = Vec::with_capacity(lengths.len());
+        let mut offset = 0;
+        for (pattern, length) in lengths.iter().enumerate() {
+            starts.set(offset);
+            ends.set(offset + length - 1);
+            patterns[offset + length - 1] = pattern;
+            offsets.push(offset);
+            offset += length;
+        }
+        let bank = Self {
+            len,
+            starts,
+            ends,
+            patterns,
+        };
+        (bank, offsets)
+    }
+}
+
+/// Many queries compiled to be searched for together in a single pass over a
+/// stamp.
+///
+/// Every value named by an `Is` or `Not` cell becomes a symbol, and each
+/// query cell becomes the set of symbols it accepts. Rows of the queries are
+/// matched along each row of the stamp with a multi-pattern Shift-And, and
+/// the queries are then matched down each column over the rows that ended
+/// there, in the manner of Baker and Bird. Cells that can't be expressed as a
+/// set of symbols (`Capture`, `SameAs` and `Matches`) are widened for the
+/// scan and their candidates are checked against the stamp afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuerySet<T: Clone + PartialEq> {
+    queries: Vec<Stamp<QueryStampPart<T>>>,
+    alphabet: Vec<T>,
+    /// Indices of queries with no cells, which are searched directly.
+    empty: Vec<usize>,
+    /// Indices of queries that must be confirmed after the scan.
+    verify: Vec<bool>,
+    rows: ShiftAnd,
+    /// For each symbol, the row pattern bits that accept it.
+    row_masks: Vec<Bits>,
+    columns: ShiftAnd,
+    /// For each distinct row pattern, the column bits of the query rows it is.
+    column_masks: Vec<Bits>,
+    /// For each column pattern, the query it belongs to.
+    column_queries: Vec<usize>,
+}
+
+impl<T: Clone + PartialEq> QuerySet<T> {
+    pub fn new(queries: Vec<Stamp<QueryStampPart<T>>>) -> Self {
+        let mut alphabet: Vec<T> = Vec::new();
+        for part in queries.iter().flat_map(|query| query.as_slice()) {
+            if let QueryStampPart::Is(values) | QueryStampPart::Not(values) = part {
+                for value in values.iter() {
+                    if !alphabet.contains(value) {
+                        alphabet.push(value.clone());
+                    }
+                }
+            }
+        }
+        let symbols = alphabet.len() + 2;
+        let symbol_class = |part: &QueryStampPart<T>| -> Vec<bool> {
+            let transparent = alphabet.len() + 1;
+            (0..symbols)
+                .map(|symbol| match part {
+                    QueryStampPart::Any
+                    | QueryStampPart::Capture(_)
+                    | QueryStampPart::SameAs(_) => true,
+                    QueryStampPart::OutOfBounds => false,
+                    QueryStampPart::Transparent => symbol == transparent,
+                    QueryStampPart::Opaque | QueryStampPart::Matches(_) => symbol != transparent,
+                    QueryStampPart::Is(values) => {
+                        matches!(alphabet.get(symbol), Some(value) if values.contains(value))
+                    }
+                    QueryStampPart::Not(values) => {
+                        !matches!(alphabet.get(symbol), Some(value) if values.contains(value))
+                    }
+                })
+                .collect()
+        };
+
+        let mut empty = Vec::new();
+        let mut verify = Vec::with_capacity(queries.len());
+        let mut distinct_rows: Vec<Vec<Vec<bool>>> = Vec::new();
+        let mut query_rows: Vec<(usize, Vec<usize>)> = Vec::new();
+        for (index, query) in queries.iter().enumerate() {
+            verify.push(query.as_slice().iter().any(|part| {
+                matches!(
+                    part,
+                    QueryStampPart::Capture(_)
+                        | QueryStampPart::SameAs(_)
+                        | QueryStampPart::Matches(_)
+                )
+            }));
+            if query.as_slice().is_empty() {
+                empty.push(index);
+                continue;
+            }
+            let rows = query
+                .rows()
+                .map(|row| {
+                    let classes: Vec<Vec<bool>> = row.iter().map(&symbol_class).collect();
+                    match distinct_rows.iter().position(|other| *other == classes) {
+                        Some(position) => position,
+                        None => {
+                            distinct_rows.push(classes);
+                            distinct_rows.len() - 1
+                        }
+                    }
+                })
+                .collect();
+            query_rows.push((index, rows));
+        }
+
+        let row_lengths: Vec<usize> = distinct_rows.iter().map(|row| row.len()).collect();
+        let (rows, row_offsets) = ShiftAnd::new(&row_lengths);
+        let mut row_masks = vec![Bits::new(rows.len); symbols];
+        for (row, offset) in distinct_rows.iter().zip(row_offsets) {
+            for (position, class) in row.iter().enumerate() {
+                for (symbol, accepted) in class.iter().enumerate() {
+                    if *accepted {
+                        row_masks[symbol].set(offset + position);
+                    }
+                }
+            }
+        }
+
+        let column_lengths: Vec<usize> = query_rows.iter().map(|(_, rows)| rows.len()).collect();
+        let (columns, column_offsets) = ShiftAnd::new(&column_lengths);
+        let mut column_masks = vec![Bits::new(columns.len); distinct_rows.len()];
+        for ((_, rows), offset) in query_rows.iter().zip(column_offsets) {
+            for (position, row) in rows.iter().enumerate() {
+                column_masks[*row].set(offset + position);
+            }
+        }
+        let column_queries = query_rows.iter().map(|(index, _)| *index).collect();
+
+        Self {
+            queries,
+            alphabet,
+            empty,
+            verify,
+            rows,
+            row_masks,
+            columns,
+            column_masks,
+            column_queries,
+        }
+    }
+
+    pub fn queries(&self) -> &[Stamp<QueryStampPart<T>>] {
+        &self.queries
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// The symbol of a cell, found by scanning the alphabet of every value
+    /// the queries mention. This is done for every cell searched, so a large
+    /// alphabet slows the whole search down.
+    fn symbol(&self, part: &StampPart<T>) -> usize {
+        match part {
+            StampPart::Use(value) => self
+                .alphabet
+                .iter()
+                .position(|symbol| symbol == value)
+                .unwrap_or(self.alphabet.len()),
+            StampPart::Transparent => self.alphabet.len() + 1,
+        }
+    }
+
+    /// Finds every query in `stamp` in one pass, returning the index of the
+    /// query that matched with its position. Matches are ordered row-major by
+    /// position, then by query index, and agree with `Stamp::find` for each
+    /// query.
+    pub fn find_all(&self, stamp: &Stamp<StampPart<T>>) -> Vec<(usize, (usize, usize))> {
+        let mut matches = Vec::new();
+        for &index in self.empty.iter() {
+            matches.extend(
+                stamp
+                    .find_iter(&self.queries[index])
+                    .map(|position| (index, position)),
+            );
+        }
+        let mut row_state = Bits::new(self.rows.len);
+        let mut column_mask = Bits::new(self.columns.len);
+        let mut column_states = vec![Bits::new(self.columns.len); stamp.width()];
+        for (y, row) in stamp.rows().enumerate() {
+            row_state.clear();
+            for (x, part) in row.iter().enumerate() {
+                row_state.step(&self.rows.starts, &self.row_masks[self.symbol(part)]);
+                column_mask.clear();
+                for end in row_state.common_ones(&self.rows.ends) {
+                    column_mask.or_assign(&self.column_masks[self.rows.patterns[end]]);
+                }
+                let column_state = &mut column_states[x];
+                column_state.step(&self.columns.starts, &column_mask);
+                for end in column_state.common_ones(&self.columns.ends) {
+                    let index = self.column_queries[self.columns.patterns[end]];
+                    let query = &self.queries[index];
+                    let position = (x + 1 - query.width(), y + 1 - query.height());
+                    if !self.verify[index] || stamp.find_at_xy(query, position.0, position.1) {
+                        matches.push((index, position));
+                    }
+                }
+            }
+        }
+        matches.sort_by_key(|&(index, (x, y))| (y, x, index));
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Predicate;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use QueryStampPart::{Any, Capture, Is, Not, SameAs};
+    use StampPart::{Transparent, Use};
+
+    fn naive(
+        stamp: &Stamp<StampPart<i32>>,
+        queries: &[Stamp<QueryStampPart<i32>>],
+    ) -> Vec<(usize, (usize, usize))> {
+        let mut matches: Vec<(usize, (usize, usize))> = queries
+            .iter()
+            .enumerate()
+            .flat_map(|(index, query)| {
+                stamp
+                    .find(query)
+                    .into_iter()
+                    .map(move |position| (index, position))
+            })
+            .collect();
+        matches.sort_by_key(|&(index, (x, y))| (y, x, index));
+        matches
+    }
+
+    #[test]
+    fn it_should_report_which_query_matched_where() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(1), Use(0)],
+            vec![Use(1), Use(0), Use(1)],
+            vec![Use(0), Use(0), Use(1)],
+        ]);
+        let queries = QuerySet::new(vec![
+            Stamp::new(vec![vec![Is(Box::new([1])), Is(Box::new([1]))]]),
+            Stamp::new(vec![vec![Is(Box::new([0]))], vec![Is(Box::new([1]))]]),
+        ]);
+        let result = queries.find_all(&stamp);
+        let expected = vec![(0, (0, 0)), (1, (2, 0))];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_should_agree_with_find_for_every_kind_of_query() {
+        let mut rng = StdRng::seed_from_u64(15);
+        let stamp = Stamp::new(
+            (0..40)
+                .map(|_| {
+                    (0..50)
+                        .map(|_| match rng.gen_range(0..5) {
+                            4 => Transparent,
+                            value => Use(value),
+                        })
+                        .collect()
+                })
+                .collect(),
+        );
+        let queries = vec![
+            Stamp::new(vec![
+                vec![Is(Box::new([0])), Any],
+                vec![Any, Is(Box::new([1, 2]))],
+            ]),
+            Stamp::new(vec![vec![Not(Box::new([3])), Not(Box::new([3]))]]),
+            Stamp::new(vec![
+                vec![QueryStampPart::Transparent],
+                vec![QueryStampPart::Opaque],
+            ]),
+            Stamp::new(vec![
+                vec![Capture('a'), SameAs('a')],
+                vec![SameAs('a'), Any],
+            ]),
+            Stamp::new(vec![vec![QueryStampPart::Matches(Predicate::new(
+                |v: &i32| *v > 1,
+            ))]]),
+            Stamp::new(vec![
+                vec![Is(Box::new([0])), Any],
+                vec![Any, Is(Box::new([1, 2]))],
+            ]),
+            Stamp::new(vec![vec![Is(Box::new([2]))]; 3]),
+            Stamp::new(vec![vec![Any; 50]; 40]),
+            Stamp::new(vec![vec![QueryStampPart::OutOfBounds]]),
+        ];
+        let query_set = QuerySet::new(queries.clone());
+        assert_eq!(query_set.find_all(&stamp), naive(&stamp, &queries));
+    }
+
+    #[test]
+    fn it_should_find_empty_queries_like_find() {
+        let stamp = Stamp::new(vec![vec![Use(0), Use(1)]]);
+        let queries = vec![
+            Stamp::new(vec![]),
+            Stamp::new(vec![vec![Is(Box::new([1]))]]),
+        ];
+        let query_set = QuerySet::new(queries.clone());
+        assert_eq!(query_set.find_all(&stamp), naive(&stamp, &queries));
+    }
+}
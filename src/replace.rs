@@ -0,0 +1,162 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Captures, Rect, Stamp, StampError, StampPart};
+
+/// A cell of a replacement stamp, resolved against the target and the
+/// captures of a match when it is stamped.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReplaceStampPart<T: Clone + PartialEq> {
+    Use(T),
+    /// Leaves the target cell as it was.
+    Keep,
+    /// Writes the cell bound by the `QueryStampPart::Capture` of that name.
+    FromCapture(char),
+    /// Writes the cell found at this offset from the written cell, read from
+    /// the target before anything is stamped. Offsets that fall outside of
+    /// the target behave like `Keep`.
+    FromOffset(isize, isize),
+}
+
+impl<T: Clone + PartialEq> From<StampPart<T>> for ReplaceStampPart<T> {
+    fn from(part: StampPart<T>) -> Self {
+        match part {
+            StampPart::Use(value) => ReplaceStampPart::Use(value),
+            StampPart::Transparent => ReplaceStampPart::Keep,
+        }
+    }
+}
+
+/// Cells to write into a stamp as `(index, part)`.
+pub(crate) type Writes<T> = Vec<(usize, StampPart<T>)>;
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Resolves a replacement stamped at `(pos_x, pos_y)` against `captures`
+    /// and the current contents of this stamp, returning the overlapped
+    /// region and the cells to write.
+    pub(crate) fn replacement_writes(
+        &self,
+        stamp: &Stamp<ReplaceStampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        captures: &Captures<T>,
+    ) -> Result<(Option<Rect>, Writes<T>), StampError> {
+        let region = match self.clip(pos_x, pos_y, stamp.width(), stamp.height()) {
+            Some(region) => region,
+            None => return Ok((None, Vec::new())),
+        };
+        let mut writes = Vec::new();
+        for y in region.y as isize..(region.y + region.height) as isize {
+            for x in region.x as isize..(region.x + region.width) as isize {
+                let replace_part =
+                    &stamp.cells[stamp.index((x - pos_x) as usize, (y - pos_y) as usize)];
+                let part = match replace_part {
+                    ReplaceStampPart::Use(value) => StampPart::Use(value.clone()),
+                    ReplaceStampPart::Keep => continue,
+                    ReplaceStampPart::FromCapture(name) => match captures.get(*name) {
+                        Some(part) => part.clone(),
+                        None => return Err(StampError::UnboundCapture(*name)),
+                    },
+                    ReplaceStampPart::FromOffset(dx, dy) => {
+                        let (from_x, from_y) = match (x.checked_add(*dx), y.checked_add(*dy)) {
+                            (Some(from_x), Some(from_y)) if from_x >= 0 && from_y >= 0 => {
+                                (from_x, from_y)
+                            }
+                            _ => continue,
+                        };
+                        match self.get_at((from_x as usize, from_y as usize)) {
+                            Some(part) => part.clone(),
+                            None => continue,
+                        }
+                    }
+                };
+                writes.push((self.index(x as usize, y as usize), part));
+            }
+        }
+        Ok((Some(region), writes))
+    }
+
+    /// Writes cells resolved by `replacement_writes`, returning whether any
+    /// of them changed.
+    pub(crate) fn write_cells(&mut self, writes: Writes<T>) -> bool {
+        let mut changed = false;
+        for (index, part) in writes {
+            if self.cells[index] != part {
+                self.cells[index] = part;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Stamps a replacement with its top left corner at `(pos_x, pos_y)`,
+    /// resolving each cell against `captures` and the current contents of
+    /// this stamp. Cells that land outside of this stamp are clipped as with
+    /// `stamp_clipped`, and the overlapped region is returned.
+    ///
+    /// Fails without writing anything if a cell refers to a capture missing
+    /// from `captures`.
+    pub fn stamp_with_captures(
+        &mut self,
+        stamp: &Stamp<ReplaceStampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        captures: &Captures<T>,
+    ) -> Result<Option<Rect>, StampError> {
+        let (region, writes) = self.replacement_writes(stamp, pos_x, pos_y, captures)?;
+        self.write_cells(writes);
+        Ok(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ReplaceStampPart::{FromCapture, FromOffset, Keep};
+    use StampPart::Use;
+
+    #[test]
+    fn it_should_write_captured_cells() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0), Use(0)]]);
+        let replace_stamp =
+            Stamp::new(vec![vec![FromCapture('a'), Keep, ReplaceStampPart::Use(5)]]);
+        let mut captures = Captures::new();
+        captures.insert('a', Use(7));
+        let result = stamp.stamp_with_captures(&replace_stamp, 0, 0, &captures);
+        assert_eq!(result, Ok(Some(Rect::new(0, 0, 3, 1))));
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(7), Use(0), Use(5)]]));
+    }
+
+    #[test]
+    fn it_should_read_offsets_from_the_state_before_stamping() {
+        let mut stamp = Stamp::new(vec![vec![Use(1), Use(2), Use(3)]]);
+        let shift_right = Stamp::new(vec![vec![FromOffset(-1, 0); 3]]);
+        stamp
+            .stamp_with_captures(&shift_right, 0, 0, &Captures::new())
+            .unwrap();
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(1), Use(2)]]));
+    }
+
+    #[test]
+    fn huge_offsets_should_keep_the_target_cell() {
+        let mut stamp = Stamp::new(vec![vec![Use(1), Use(2)]]);
+        let far = Stamp::new(vec![vec![
+            FromOffset(0, isize::MIN),
+            FromOffset(isize::MAX, 0),
+        ]]);
+        stamp
+            .stamp_with_captures(&far, 0, 0, &Captures::new())
+            .unwrap();
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(2)]]));
+    }
+
+    #[test]
+    fn it_should_error_without_writing_if_a_capture_is_unbound() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0)]]);
+        let replace_stamp = Stamp::new(vec![vec![ReplaceStampPart::Use(5), FromCapture('b')]]);
+        let result = stamp.stamp_with_captures(&replace_stamp, 0, 0, &Captures::new());
+        assert_eq!(result, Err(StampError::UnboundCapture('b')));
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(0), Use(0)]]));
+    }
+}
@@ -0,0 +1,206 @@
+#[cfg(feature = "rand")]
+use rand::Rng;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Captures, QueryStampPart, Rect, ReplaceStampPart, Stamp, StampPart};
+
+/// A rewrite: wherever `query` matches, stamp `replacement` with its top left
+/// corner at the match position, resolving it against the captures of the
+/// match. Any part of the replacement that falls outside of the target is
+/// clipped, and a replacement that refers to a capture the query doesn't bind
+/// leaves the target unchanged. Rewrites that leave every cell as it was are
+/// not counted.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rule<T: Clone + PartialEq> {
+    pub query: Stamp<QueryStampPart<T>>,
+    pub replacement: Stamp<ReplaceStampPart<T>>,
+}
+
+impl<T: Clone + PartialEq> Rule<T> {
+    /// Builds a rule from a replacement of either `StampPart`s, where
+    /// `Transparent` keeps the target cell, or `ReplaceStampPart`s.
+    pub fn new<R>(query: Stamp<QueryStampPart<T>>, replacement: Stamp<R>) -> Self
+    where
+        R: Clone + PartialEq + Into<ReplaceStampPart<T>>,
+    {
+        Self {
+            query,
+            replacement: replacement.map(|part| part.clone().into()),
+        }
+    }
+
+    /// The region covered by a match of this rule at `(x, y)`, taking in both
+    /// the query and the replacement.
+    pub(crate) fn footprint(&self, x: usize, y: usize) -> Rect {
+        Rect::new(
+            x,
+            y,
+            self.query.width().max(self.replacement.width()),
+            self.query.height().max(self.replacement.height()),
+        )
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Stamps the replacement of `rule` at `(x, y)`, returning whether it
+    /// could be resolved against `captures` and changed at least one cell.
+    pub(crate) fn apply_at(
+        &mut self,
+        rule: &Rule<T>,
+        x: usize,
+        y: usize,
+        captures: &Captures<T>,
+    ) -> bool {
+        match self.replacement_writes(&rule.replacement, x as isize, y as isize, captures) {
+            Ok((_, writes)) => self.write_cells(writes),
+            Err(_) => false,
+        }
+    }
+
+    /// Rewrites the first match of `rule` in row-major order. Returns the
+    /// number of rewrites made, which is at most one.
+    pub fn apply_first(&mut self, rule: &Rule<T>) -> usize {
+        let first = self.find_first(&rule.query).and_then(|(x, y)| {
+            self.captures_at_position(&rule.query, x, y)
+                .map(|captures| ((x, y), captures))
+        });
+        match first {
+            Some(((x, y), captures)) => self.apply_at(rule, x, y, &captures) as usize,
+            None => 0,
+        }
+    }
+
+    /// Finds every match of `rule` against the current state, then rewrites
+    /// as many as possible in row-major order, skipping any whose footprint
+    /// overlaps one already chosen. Returns the number of rewrites made.
+    pub fn apply_all_non_overlapping(&mut self, rule: &Rule<T>) -> usize {
+        let mut chosen: Vec<(Rect, Captures<T>)> = Vec::new();
+        for ((x, y), captures) in self.find_captures(&rule.query) {
+            let footprint = rule.footprint(x, y);
+            if chosen
+                .iter()
+                .all(|(other, _)| !other.intersects(&footprint))
+            {
+                chosen.push((footprint, captures));
+            }
+        }
+        chosen
+            .iter()
+            .filter(|(footprint, captures)| self.apply_at(rule, footprint.x, footprint.y, captures))
+            .count()
+    }
+
+    /// Rewrites one match of `rule` chosen uniformly at random. Returns the
+    /// number of rewrites made, which is at most one.
+    #[cfg(feature = "rand")]
+    pub fn apply_random<R: Rng + ?Sized>(&mut self, rule: &Rule<T>, rng: &mut R) -> usize {
+        let matches = self.find_captures(&rule.query);
+        if matches.is_empty() {
+            return 0;
+        }
+        let ((x, y), captures) = &matches[rng.gen_range(0..matches.len())];
+        self.apply_at(rule, *x, *y, captures) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "rand")]
+    use rand::rngs::StdRng;
+    #[cfg(feature = "rand")]
+    use rand::SeedableRng;
+    use QueryStampPart::{Capture, Is};
+    use StampPart::Use;
+
+    fn zeros_to_ones() -> Rule<i32> {
+        Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([0])), Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![Use(1), Use(1)]]),
+        )
+    }
+
+    #[test]
+    fn apply_first_should_rewrite_the_first_match() {
+        let mut stamp = Stamp::new(vec![
+            vec![Use(1), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        assert_eq!(stamp.apply_first(&zeros_to_ones()), 1);
+        let expected = Stamp::new(vec![
+            vec![Use(1), Use(1), Use(1)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn apply_first_should_return_zero_if_nothing_matches() {
+        let mut stamp = Stamp::new(vec![vec![Use(1), Use(0), Use(1)]]);
+        assert_eq!(stamp.apply_first(&zeros_to_ones()), 0);
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(1), Use(0), Use(1)]]));
+    }
+
+    #[test]
+    fn apply_all_non_overlapping_should_skip_overlapping_matches() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0), Use(0), Use(0), Use(0)]]);
+        assert_eq!(stamp.apply_all_non_overlapping(&zeros_to_ones()), 2);
+        let expected = Stamp::new(vec![vec![Use(1), Use(1), Use(1), Use(1), Use(0)]]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn apply_random_should_rewrite_one_match() {
+        let mut stamp = Stamp::new(vec![vec![Use(0), Use(0), Use(0), Use(0), Use(0)]]);
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(stamp.apply_random(&zeros_to_ones(), &mut rng), 1);
+        let ones = stamp
+            .as_slice()
+            .iter()
+            .filter(|part| **part == Use(1))
+            .count();
+        assert_eq!(ones, 2);
+    }
+
+    #[test]
+    fn it_should_copy_captured_cells_into_the_replacement() {
+        let mut stamp = Stamp::new(vec![vec![Use(3), Use(0), Use(4), Use(0)]]);
+        let spread = Rule::new(
+            Stamp::new(vec![vec![Capture('a'), Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![
+                ReplaceStampPart::Keep,
+                ReplaceStampPart::FromCapture('a'),
+            ]]),
+        );
+        assert_eq!(stamp.apply_all_non_overlapping(&spread), 2);
+        let expected = Stamp::new(vec![vec![Use(3), Use(3), Use(4), Use(4)]]);
+        assert_eq!(stamp, expected);
+    }
+
+    #[test]
+    fn it_should_not_rewrite_if_the_replacement_refers_to_an_unbound_capture() {
+        let mut stamp = Stamp::new(vec![vec![Use(0)]]);
+        let rule = Rule::new(
+            Stamp::new(vec![vec![Is(Box::new([0]))]]),
+            Stamp::new(vec![vec![ReplaceStampPart::FromCapture('a')]]),
+        );
+        assert_eq!(stamp.apply_first(&rule), 0);
+        assert_eq!(stamp, Stamp::new(vec![vec![Use(0)]]));
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn apply_random_should_be_reproducible_for_a_seed() {
+        let start = Stamp::new(vec![vec![Use(0); 9]]);
+        let run = |seed| {
+            let mut stamp = start.clone();
+            let mut rng = StdRng::seed_from_u64(seed);
+            stamp.apply_random(&zeros_to_ones(), &mut rng);
+            stamp
+        };
+        assert_eq!(run(3), run(3));
+    }
+}
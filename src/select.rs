@@ -0,0 +1,256 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{QueryStampPart, Stamp, StampPart};
+
+/// How `find_non_overlapping` chooses between matches that overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Strategy {
+    /// Repeatedly keeps the match that overlaps the fewest others that are
+    /// still available, breaking ties in row-major order. Takes O(n log n)
+    /// time in the number of matches, and is usually close to the largest set.
+    Greedy,
+    /// Keeps each match in row-major order unless it overlaps one already kept.
+    RowMajor,
+    /// Keeps the largest possible set of matches. Overlapping clusters are
+    /// searched exhaustively, so this can be slow when many matches overlap
+    /// one another.
+    MaximumSet,
+}
+
+/// For each match, the indices of the other matches it overlaps. Every match
+/// is `width` by `height`.
+fn overlaps(matches: &[(usize, usize)], width: usize, height: usize) -> Vec<Vec<usize>> {
+    let positions: HashMap<(usize, usize), usize> = matches
+        .iter()
+        .enumerate()
+        .map(|(index, &position)| (position, index))
+        .collect();
+    matches
+        .iter()
+        .enumerate()
+        .map(|(index, &(x, y))| {
+            let mut neighbours = Vec::new();
+            for other_y in (y + 1).saturating_sub(height)..y + height {
+                for other_x in (x + 1).saturating_sub(width)..x + width {
+                    if let Some(&other) = positions.get(&(other_x, other_y)) {
+                        if other != index {
+                            neighbours.push(other);
+                        }
+                    }
+                }
+            }
+            neighbours
+        })
+        .collect()
+}
+
+fn row_major(overlaps: &[Vec<usize>]) -> Vec<usize> {
+    let mut blocked = vec![false; overlaps.len()];
+    let mut chosen = Vec::new();
+    for index in 0..overlaps.len() {
+        if !blocked[index] {
+            chosen.push(index);
+            overlaps[index]
+                .iter()
+                .for_each(|&other| blocked[other] = true);
+        }
+    }
+    chosen
+}
+
+fn greedy(overlaps: &[Vec<usize>]) -> Vec<usize> {
+    let mut available = vec![true; overlaps.len()];
+    let mut degrees: Vec<usize> = overlaps.iter().map(|neighbours| neighbours.len()).collect();
+    // Entries go stale when a match is taken or its degree drops, in which
+    // case a fresh entry has been pushed and the stale one is skipped.
+    let mut queue: BinaryHeap<Reverse<(usize, usize)>> = degrees
+        .iter()
+        .enumerate()
+        .map(|(index, &degree)| Reverse((degree, index)))
+        .collect();
+    let mut chosen = Vec::new();
+    while let Some(Reverse((degree, index))) = queue.pop() {
+        if !available[index] || degree != degrees[index] {
+            continue;
+        }
+        chosen.push(index);
+        available[index] = false;
+        for &removed in overlaps[index].iter() {
+            if available[removed] {
+                available[removed] = false;
+                for &other in overlaps[removed].iter() {
+                    degrees[other] -= 1;
+                    if available[other] {
+                        queue.push(Reverse((degrees[other], other)));
+                    }
+                }
+            }
+        }
+    }
+    chosen
+}
+
+/// Branch and bound search for the largest set of non-overlapping matches
+/// among `candidates`.
+fn maximum_within(
+    overlaps: &[Vec<usize>],
+    candidates: &[usize],
+    chosen: &mut Vec<usize>,
+    best: &mut Vec<usize>,
+) {
+    if chosen.len() + candidates.len() <= best.len() {
+        return;
+    }
+    let (&first, rest) = match candidates.split_first() {
+        Some(split) => split,
+        None => {
+            *best = chosen.clone();
+            return;
+        }
+    };
+    let without_first: Vec<usize> = rest
+        .iter()
+        .copied()
+        .filter(|other| !overlaps[first].contains(other))
+        .collect();
+    chosen.push(first);
+    maximum_within(overlaps, &without_first, chosen, best);
+    chosen.pop();
+    if without_first.len() < rest.len() {
+        maximum_within(overlaps, rest, chosen, best);
+    }
+}
+
+fn maximum(overlaps: &[Vec<usize>]) -> Vec<usize> {
+    let mut component = vec![usize::MAX; overlaps.len()];
+    let mut chosen = Vec::new();
+    for start in 0..overlaps.len() {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        let mut members = vec![start];
+        component[start] = start;
+        let mut next = 0;
+        while next < members.len() {
+            for &other in overlaps[members[next]].iter() {
+                if component[other] == usize::MAX {
+                    component[other] = start;
+                    members.push(other);
+                }
+            }
+            next += 1;
+        }
+        members.sort_by_key(|&index| Reverse(overlaps[index].len()));
+        let mut best = Vec::new();
+        maximum_within(overlaps, &members, &mut Vec::new(), &mut best);
+        chosen.extend(best);
+    }
+    chosen
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Finds matches of `query` that don't overlap one another, so every one
+    /// of them can be stamped over safely. Returns them in row-major order.
+    pub fn find_non_overlapping(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        strategy: Strategy,
+    ) -> Vec<(usize, usize)> {
+        let matches = self.find(query);
+        if query.width() == 0 || query.height() == 0 {
+            return matches;
+        }
+        let overlaps = overlaps(&matches, query.width(), query.height());
+        let mut chosen = match strategy {
+            Strategy::Greedy => greedy(&overlaps),
+            Strategy::RowMajor => row_major(&overlaps),
+            Strategy::MaximumSet => maximum(&overlaps),
+        };
+        chosen.sort_unstable();
+        chosen.into_iter().map(|index| matches[index]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use QueryStampPart::{Any, Is};
+    use StampPart::Use;
+
+    fn crossing() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(0), Use(1), Use(0), Use(0)],
+            vec![Use(1), Use(0), Use(1), Use(0)],
+            vec![Use(0), Use(0), Use(0), Use(0)],
+        ])
+    }
+
+    fn corner() -> Stamp<QueryStampPart<i32>> {
+        Stamp::new(vec![vec![Is(Box::new([1])), Any], vec![Any, Any]])
+    }
+
+    #[test]
+    fn row_major_should_keep_the_earliest_matches() {
+        let result = crossing().find_non_overlapping(&corner(), Strategy::RowMajor);
+        assert_eq!(result, vec![(1, 0)]);
+    }
+
+    #[test]
+    fn greedy_should_prefer_matches_that_overlap_the_fewest_others() {
+        let result = crossing().find_non_overlapping(&corner(), Strategy::Greedy);
+        assert_eq!(result, vec![(0, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn greedy_should_handle_many_matches() {
+        let stamp = Stamp::new(vec![vec![Use(0); 300]; 300]);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([0])); 2]; 2]);
+        let result = stamp.find_non_overlapping(&query_stamp, Strategy::Greedy);
+        assert_eq!(result.len(), 150 * 150);
+        assert!(result.iter().all(|&(x, y)| x % 2 == 0 && y % 2 == 0));
+    }
+
+    #[test]
+    fn maximum_set_should_keep_as_many_matches_as_possible() {
+        let mut rng = StdRng::seed_from_u64(20);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([1])), Any], vec![Any, Any]]);
+        for _ in 0..20 {
+            let stamp = Stamp::new(
+                (0..5)
+                    .map(|_| (0..5).map(|_| Use(rng.gen_range(0..2))).collect())
+                    .collect(),
+            );
+            let matches = stamp.find(&query_stamp);
+            let largest = (0u32..1 << matches.len())
+                .filter(|set| {
+                    let rects: Vec<Rect> = (0..matches.len())
+                        .filter(|index| set & (1 << index) != 0)
+                        .map(|index| Rect::new(matches[index].0, matches[index].1, 2, 2))
+                        .collect();
+                    rects.iter().enumerate().all(|(index, rect)| {
+                        rects[index + 1..]
+                            .iter()
+                            .all(|other| !rect.intersects(other))
+                    })
+                })
+                .map(|set| set.count_ones() as usize)
+                .max()
+                .unwrap();
+            let result = stamp.find_non_overlapping(&query_stamp, Strategy::MaximumSet);
+            assert_eq!(result.len(), largest);
+            for (index, &(x, y)) in result.iter().enumerate() {
+                for &(other_x, other_y) in result[index + 1..].iter() {
+                    assert!(!Rect::new(x, y, 2, 2).intersects(&Rect::new(other_x, other_y, 2, 2)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{BlendMode, CaptureMatcher, Captures, QueryStampPart, Rect, Stamp, StampPart};
+
+/// One of the eight rotations and reflections of a rectangle (the D4 group).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Transform {
+    Identity,
+    /// Clockwise, as `Stamp::rotate_90`.
+    Rotate90,
+    Rotate180,
+    /// Counter-clockwise, as `Stamp::rotate_n90`.
+    Rotate270,
+    /// Mirrors left and right, as `Stamp::flip_horizontal`.
+    FlipHorizontal,
+    /// Mirrors top and bottom, as `Stamp::flip_vertical`.
+    FlipVertical,
+    /// Mirrors across the top-left to bottom-right diagonal.
+    Transpose,
+    /// Mirrors across the top-right to bottom-left diagonal.
+    AntiTranspose,
+}
+
+impl Transform {
+    pub const ALL: [Transform; 8] = [
+        Transform::Identity,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::FlipHorizontal,
+        Transform::FlipVertical,
+        Transform::Transpose,
+        Transform::AntiTranspose,
+    ];
+
+    fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Transpose
+                | Transform::AntiTranspose
+        )
+    }
+
+    /// The `(width, height)` of a `width` by `height` stamp after this transform.
+    pub fn dimensions(self, width: usize, height: usize) -> (usize, usize) {
+        if self.swaps_axes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Maps a coordinate in the transformed stamp back to the coordinate it
+    /// was read from in a `width` by `height` source stamp.
+    pub fn source_coord(self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Transform::Identity => (x, y),
+            Transform::Rotate90 => (y, height - 1 - x),
+            Transform::Rotate180 => (width - 1 - x, height - 1 - y),
+            Transform::Rotate270 => (width - 1 - y, x),
+            Transform::FlipHorizontal => (width - 1 - x, y),
+            Transform::FlipVertical => (x, height - 1 - y),
+            Transform::Transpose => (y, x),
+            Transform::AntiTranspose => (width - 1 - y, height - 1 - x),
+        }
+    }
+}
+
+/// A set of transforms to try a query under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Symmetry {
+    Identity,
+    MirrorHorizontal,
+    MirrorVertical,
+    Rotations,
+    All,
+}
+
+impl Symmetry {
+    pub fn transforms(self) -> &'static [Transform] {
+        match self {
+            Symmetry::Identity => &[Transform::Identity],
+            Symmetry::MirrorHorizontal => &[Transform::Identity, Transform::FlipHorizontal],
+            Symmetry::MirrorVertical => &[Transform::Identity, Transform::FlipVertical],
+            Symmetry::Rotations => &[
+                Transform::Identity,
+                Transform::Rotate90,
+                Transform::Rotate180,
+                Transform::Rotate270,
+            ],
+            Symmetry::All => &Transform::ALL,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<T> {
+    /// Returns a copy of this stamp with `transform` applied.
+    pub fn transformed(&self, transform: Transform) -> Self {
+        let (width, height) = transform.dimensions(self.width, self.height);
+        let mut cells = Vec::with_capacity(self.cells.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = transform.source_coord(x, y, self.width, self.height);
+                cells.push(self.cells[self.index(src_x, src_y)].clone());
+            }
+        }
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    /// Returns every distinct orientation of this stamp under the D4 group,
+    /// tagged with the transform that produced it. Symmetric stamps yield
+    /// fewer than eight variants.
+    pub fn variants(&self) -> Vec<(Transform, Self)> {
+        let mut variants: Vec<(Transform, Self)> = Vec::with_capacity(8);
+        for &transform in Transform::ALL.iter() {
+            let variant = self.transformed(transform);
+            if variants.iter().all(|(_, existing)| *existing != variant) {
+                variants.push((transform, variant));
+            }
+        }
+        variants
+    }
+
+    /// Returns the same stamp for every orientation of a pattern, so that
+    /// rotated or mirrored copies can be recognised as duplicates.
+    pub fn canonical(&self) -> Self
+    where
+        T: Ord,
+    {
+        Transform::ALL
+            .iter()
+            .map(|&transform| self.transformed(transform))
+            .min_by(|a, b| {
+                (a.width, a.height, a.as_slice()).cmp(&(b.width, b.height, b.as_slice()))
+            })
+            .expect("the D4 group is never empty")
+    }
+}
+
+impl<T: Clone + PartialEq> Stamp<StampPart<T>> {
+    /// Finds `query` under every transform in `symmetry`, returning each match
+    /// with the transform of the query that produced it. Transforms that leave
+    /// the query unchanged are only searched once.
+    ///
+    /// Matches are grouped by transform, in the order of `Symmetry::transforms`,
+    /// and are row-major within each group.
+    pub fn find_with_symmetry(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        symmetry: Symmetry,
+    ) -> Vec<((usize, usize), Transform)> {
+        let mut searched: Vec<Stamp<QueryStampPart<T>>> = Vec::new();
+        let mut matches = Vec::new();
+        for &transform in symmetry.transforms() {
+            let transformed = query.transformed(transform);
+            if searched.contains(&transformed) {
+                continue;
+            }
+            matches.extend(
+                self.find_iter(&transformed)
+                    .map(|position| (position, transform)),
+            );
+            searched.push(transformed);
+        }
+        matches
+    }
+
+    /// Matches `query` under `transform` at the given position, reading the
+    /// query through the transform rather than copying it, and returns the
+    /// values bound by its capture cells if it matched.
+    pub(crate) fn captures_at_position_transformed(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        transform: Transform,
+        pos_x: usize,
+        pos_y: usize,
+    ) -> Option<Captures<T>> {
+        let (width, height) = transform.dimensions(query.width, query.height);
+        if !self.fits(pos_x, pos_y, width, height) {
+            return None;
+        }
+        let mut matcher = CaptureMatcher::new();
+        for y in 0..height {
+            let this_row = &self.cells[self.index(pos_x, pos_y + y)..];
+            for (x, part) in this_row[..width].iter().enumerate() {
+                let (src_x, src_y) = transform.source_coord(x, y, query.width, query.height);
+                if !matcher.check(&query.cells[query.index(src_x, src_y)], part) {
+                    return None;
+                }
+            }
+        }
+        matcher.finish()
+    }
+
+    /// Lazily yields every position at which `query` matches with `transform`
+    /// applied to it, in row-major order.
+    pub(crate) fn find_iter_transformed<'a>(
+        &'a self,
+        query: &'a Stamp<QueryStampPart<T>>,
+        transform: Transform,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (width, height) = transform.dimensions(query.width, query.height);
+        let (x_range, y_range) = self.origin_ranges_for(width, height);
+        y_range
+            .flat_map(move |y| x_range.clone().map(move |x| (x, y)))
+            .filter(move |&(x, y)| {
+                self.captures_at_position_transformed(query, transform, x, y)
+                    .is_some()
+            })
+    }
+
+    /// Finds `query` with `transform` applied to it, in row-major order. The
+    /// same as searching for `query.transformed(transform)` without building
+    /// the transformed copy.
+    pub fn find_transformed(
+        &self,
+        query: &Stamp<QueryStampPart<T>>,
+        transform: Transform,
+    ) -> Vec<(usize, usize)> {
+        self.find_iter_transformed(query, transform).collect()
+    }
+
+    /// Stamps `stamp` with `transform` applied to it, as `stamp_clipped` would
+    /// stamp `stamp.transformed(transform)`, without building the transformed
+    /// copy. Returns the region that was overlapped, or `None` if the stamp
+    /// fell entirely outside.
+    pub fn stamp_transformed(
+        &mut self,
+        stamp: &Stamp<StampPart<T>>,
+        pos_x: isize,
+        pos_y: isize,
+        transform: Transform,
+    ) -> Option<Rect> {
+        self.stamp_blended(stamp, pos_x, pos_y, transform, BlendMode::Overwrite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryStampPart::{Any, Is};
+    use StampPart::Use;
+
+    type Operation = fn(&mut Stamp<StampPart<i32>>);
+
+    fn asymmetric() -> Stamp<StampPart<i32>> {
+        Stamp::new(vec![
+            vec![Use(1), Use(2), Use(3)],
+            vec![Use(4), Use(5), Use(6)],
+        ])
+    }
+
+    #[test]
+    fn transformed_should_agree_with_the_in_place_operations() {
+        let operations: [(Transform, Operation); 6] = [
+            (Transform::Identity, |_| {}),
+            (Transform::Rotate90, Stamp::rotate_90),
+            (Transform::Rotate180, Stamp::rotate_180),
+            (Transform::Rotate270, Stamp::rotate_n90),
+            (Transform::FlipHorizontal, Stamp::flip_horizontal),
+            (Transform::FlipVertical, Stamp::flip_vertical),
+        ];
+        for (transform, operation) in operations.iter() {
+            let mut expected = asymmetric();
+            operation(&mut expected);
+            assert_eq!(asymmetric().transformed(*transform), expected);
+        }
+    }
+
+    #[test]
+    fn it_should_transpose_across_both_diagonals() {
+        let transposed = Stamp::new(vec![
+            vec![Use(1), Use(4)],
+            vec![Use(2), Use(5)],
+            vec![Use(3), Use(6)],
+        ]);
+        let anti_transposed = Stamp::new(vec![
+            vec![Use(6), Use(3)],
+            vec![Use(5), Use(2)],
+            vec![Use(4), Use(1)],
+        ]);
+        assert_eq!(asymmetric().transformed(Transform::Transpose), transposed);
+        assert_eq!(
+            asymmetric().transformed(Transform::AntiTranspose),
+            anti_transposed
+        );
+    }
+
+    #[test]
+    fn variants_should_include_all_eight_orientations_of_an_asymmetric_stamp() {
+        let variants = asymmetric().variants();
+        let transforms: Vec<Transform> = variants.iter().map(|(t, _)| *t).collect();
+        assert_eq!(transforms, Transform::ALL.to_vec());
+    }
+
+    #[test]
+    fn variants_should_drop_duplicate_orientations() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(0), Use(1)],
+            vec![Use(0), Use(0), Use(0)],
+        ]);
+        let variants = stamp.variants();
+        let transforms: Vec<Transform> = variants.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            transforms,
+            vec![
+                Transform::Identity,
+                Transform::Rotate90,
+                Transform::Rotate180,
+                Transform::Rotate270
+            ]
+        );
+    }
+
+    #[test]
+    fn canonical_should_be_the_same_for_every_orientation() {
+        let canonical = asymmetric().canonical();
+        for (_, variant) in asymmetric().variants() {
+            assert_eq!(variant.canonical(), canonical);
+        }
+    }
+
+    #[test]
+    fn find_with_symmetry_should_report_the_transform_of_each_match() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(0)],
+            vec![Use(0), Use(0), Use(1)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![Is(Box::new([1])), Is(Box::new([0]))]]);
+        let result = stamp.find_with_symmetry(&query_stamp, Symmetry::Rotations);
+        let expected = vec![
+            ((0, 0), Transform::Identity),
+            ((0, 0), Transform::Rotate90),
+            ((1, 2), Transform::Rotate180),
+            ((2, 1), Transform::Rotate270),
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn find_with_symmetry_should_skip_transforms_that_do_not_change_the_query() {
+        let stamp = Stamp::new(vec![vec![Use(0), Use(0)], vec![Use(0), Use(0)]]);
+        let query_stamp = Stamp::new(vec![vec![Any, Any], vec![Any, Any]]);
+        let result = stamp.find_with_symmetry(&query_stamp, Symmetry::All);
+        assert_eq!(result, vec![((0, 0), Transform::Identity)]);
+    }
+
+    #[test]
+    fn find_transformed_should_match_searching_the_transformed_query() {
+        let stamp = Stamp::new(vec![
+            vec![Use(1), Use(2), Use(1), Use(4)],
+            vec![Use(4), Use(5), Use(2), Use(1)],
+            vec![Use(1), Use(4), Use(1), Use(2)],
+        ]);
+        let query_stamp = Stamp::new(vec![vec![
+            Is(Box::new([1])),
+            Any,
+            QueryStampPart::Capture('a'),
+        ]]);
+        for &transform in Transform::ALL.iter() {
+            assert_eq!(
+                stamp.find_transformed(&query_stamp, transform),
+                stamp.find(&query_stamp.transformed(transform))
+            );
+        }
+    }
+
+    #[test]
+    fn stamp_transformed_should_match_stamping_the_transformed_stamp() {
+        let brush = Stamp::new(vec![
+            vec![Use(1), StampPart::Transparent, Use(3)],
+            vec![Use(4), Use(5), Use(6)],
+        ]);
+        for &transform in Transform::ALL.iter() {
+            let mut result = Stamp::new(vec![vec![Use(0); 3]; 3]);
+            let region = result.stamp_transformed(&brush, 1, -1, transform);
+            let mut expected = Stamp::new(vec![vec![Use(0); 3]; 3]);
+            let expected_region = expected.stamp_clipped(&brush.transformed(transform), 1, -1);
+            assert_eq!(result, expected);
+            assert_eq!(region, expected_region);
+        }
+    }
+}